@@ -0,0 +1,7 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS device (
+  id INTEGER PRIMARY KEY,
+  label TEXT NOT NULL,
+  fingerprint TEXT NOT NULL UNIQUE,
+  revoked BOOLEAN NOT NULL DEFAULT 0
+);
@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE website_account DROP COLUMN updated_at;
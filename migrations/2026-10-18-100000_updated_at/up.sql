@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE website_account ADD COLUMN updated_at BIGINT NOT NULL DEFAULT 0;
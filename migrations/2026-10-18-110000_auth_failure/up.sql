@@ -0,0 +1,6 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS auth_failure (
+  peer TEXT PRIMARY KEY NOT NULL,
+  failures INTEGER NOT NULL,
+  locked_until BIGINT NOT NULL
+);
@@ -0,0 +1,11 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS audit_log (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  timestamp BIGINT NOT NULL,
+  peer TEXT NOT NULL,
+  action TEXT NOT NULL,
+  entry_id INTEGER,
+  success BOOLEAN NOT NULL,
+  prev_hash TEXT NOT NULL,
+  hash TEXT NOT NULL
+);
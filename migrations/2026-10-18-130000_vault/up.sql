@@ -0,0 +1,5 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS vault (
+  id INTEGER PRIMARY KEY NOT NULL CHECK (id = 1),
+  password_hash TEXT NOT NULL
+);
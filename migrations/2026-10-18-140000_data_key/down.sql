@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE vault DROP COLUMN wrapped_key;
+ALTER TABLE vault DROP COLUMN key_salt;
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE vault ADD COLUMN key_salt TEXT;
+ALTER TABLE vault ADD COLUMN wrapped_key TEXT;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS recovery_code;
+DROP TABLE IF EXISTS totp;
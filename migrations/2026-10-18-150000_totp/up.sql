@@ -0,0 +1,12 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS totp (
+  id INTEGER PRIMARY KEY NOT NULL CHECK (id = 1),
+  secret TEXT NOT NULL,
+  confirmed BOOLEAN NOT NULL DEFAULT 0,
+  last_step BIGINT
+);
+
+CREATE TABLE IF NOT EXISTS recovery_code (
+  id INTEGER PRIMARY KEY,
+  code_hash TEXT NOT NULL UNIQUE
+);
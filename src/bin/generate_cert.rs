@@ -0,0 +1,103 @@
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+const CERT_PATH: &str = "./you_should_not_pass/cert.pem";
+const KEY_PATH: &str = "./you_should_not_pass/key.pem";
+
+/// generate a self-signed certificate for local use,
+/// and point `TLS_CERT_PATH` and `TLS_KEY_PATH` in `.env` to it
+fn main() -> Result<(), Box<dyn Error>> {
+    let names = vec![
+        "localhost".to_string(),
+        "127.0.0.1".to_string(),
+        "::1".to_string(),
+    ];
+    let cert = rcgen::generate_simple_self_signed(names)?;
+    write_cert(&cert, Path::new(CERT_PATH), Path::new(KEY_PATH))?;
+
+    let env = std::fs::read_to_string(".env").unwrap_or_default();
+    std::fs::write(".env", with_tls_paths(&env))?;
+    Ok(())
+}
+
+/// write the certificate and its key as PEM, creating their directories first
+fn write_cert(cert: &rcgen::CertifiedKey, cert_path: &Path, key_path: &Path) -> io::Result<()> {
+    for path in [cert_path, key_path] {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+    }
+
+    let mut file = File::create(cert_path)?;
+    file.write_all(cert.cert.pem().as_bytes())?;
+
+    // the key is only for us
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(key_path)?;
+    file.write_all(cert.key_pair.serialize_pem().as_bytes())
+}
+
+/// `env` with `TLS_CERT_PATH` and `TLS_KEY_PATH` replaced, so running it again adds nothing
+fn with_tls_paths(env: &str) -> String {
+    let mut res: String = env
+        .lines()
+        .filter(|line| !line.starts_with("TLS_CERT_PATH=") && !line.starts_with("TLS_KEY_PATH="))
+        .map(|line| format!("{}\n", line))
+        .collect();
+    res.push_str(&format!(
+        "TLS_CERT_PATH={}\nTLS_KEY_PATH={}\n",
+        CERT_PATH, KEY_PATH
+    ));
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+    use tokio_rustls::rustls::pki_types::pem::PemObject;
+
+    use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+    use super::*;
+
+    #[test]
+    fn test_write_cert() {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+
+        // the directory does not exist yet
+        let dir = std::env::temp_dir().join(format!("ysnp_test_write_cert_{}", std::process::id()));
+        let cert_path = dir.join("you_should_not_pass").join("cert.pem");
+        let key_path = dir.join("you_should_not_pass").join("key.pem");
+        write_cert(&cert, &cert_path, &key_path).unwrap();
+
+        let der = CertificateDer::from_pem_file(&cert_path).unwrap();
+        assert_eq!(&der, cert.cert.der());
+        assert!(PrivateKeyDer::from_pem_file(&key_path).is_ok());
+        let mode = std::fs::metadata(&key_path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // running it again overwrites both
+        write_cert(&cert, &cert_path, &key_path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_with_tls_paths() {
+        let env = with_tls_paths("KEY=abc\nDATABASE_URL=file:db");
+        assert_eq!(
+            env,
+            format!(
+                "KEY=abc\nDATABASE_URL=file:db\nTLS_CERT_PATH={}\nTLS_KEY_PATH={}\n",
+                CERT_PATH, KEY_PATH
+            )
+        );
+        assert_eq!(with_tls_paths(&env), env);
+    }
+}
@@ -0,0 +1,499 @@
+use std::error::Error;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, Subcommand};
+use serde_json::json;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpStream, UnixStream};
+use you_should_not_pass::client::{AccountPatch, Client, ClientError, Event, NewAccount, Page};
+use you_should_not_pass::db::SortKey;
+use you_should_not_pass::tls;
+
+/// command-line client of the you_should_not_pass daemon
+#[derive(Parser)]
+#[command(name = "ysnp")]
+struct Cli {
+    /// address of the daemon
+    #[arg(long, env = "YSNP_ADDR", default_value = "127.0.0.1:6123")]
+    addr: String,
+    /// connect to the unix socket of the daemon instead
+    #[arg(long, env = "YSNP_SOCKET")]
+    socket: Option<PathBuf>,
+    /// speak TLS to the daemon, trusting this PEM certificate, its own or its CA
+    #[arg(long, env = "YSNP_TLS_CA", conflicts_with = "socket")]
+    tls_ca: Option<PathBuf>,
+    /// present this PEM client certificate, an enrolled device needs no login
+    #[arg(long, env = "YSNP_TLS_CERT", requires_all = ["tls_ca", "tls_key"])]
+    tls_cert: Option<PathBuf>,
+    /// the PEM private key of `--tls-cert`
+    #[arg(long, env = "YSNP_TLS_KEY", requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+    /// print JSON instead of tables
+    #[arg(long)]
+    json: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// check the password and keep the session for the next commands
+    Login,
+    /// end the session
+    Logout,
+    /// change the master password of the vault
+    Passwd,
+    /// lock the vault until the next login
+    Lock,
+    /// ask for a TOTP code at login as well
+    Totp {
+        #[command(subcommand)]
+        command: TotpCommand,
+    },
+    /// list the website accounts, or the ones matching QUERY
+    Ls {
+        query: Option<String>,
+        #[arg(long)]
+        limit: Option<usize>,
+        #[arg(long)]
+        offset: Option<usize>,
+        /// id, name, url or modified, search results keep their own order
+        #[arg(long, conflicts_with = "query")]
+        sort: Option<SortKey>,
+    },
+    /// print the password of a website account
+    Show { id: i32 },
+    /// add a website account, the password is asked for
+    Add {
+        #[arg(long)]
+        account: String,
+        #[arg(long)]
+        url: String,
+        #[arg(long)]
+        name: Option<String>,
+        #[arg(long)]
+        note: Option<String>,
+    },
+    /// change a website account, the fields left out are kept
+    Edit {
+        id: i32,
+        #[arg(long)]
+        account: Option<String>,
+        #[arg(long)]
+        url: Option<String>,
+        #[arg(long)]
+        name: Option<String>,
+        #[arg(long)]
+        note: Option<String>,
+        /// remove the site name
+        #[arg(long, conflicts_with = "name")]
+        clear_name: bool,
+        /// remove the note
+        #[arg(long, conflicts_with = "note")]
+        clear_note: bool,
+        /// ask for a new password
+        #[arg(long)]
+        password: bool,
+    },
+    /// delete a website account
+    Rm { id: i32 },
+    /// check which sites are not reachable any more
+    CheckLinks,
+    /// print the changes of the website accounts as they happen
+    Watch,
+}
+
+#[derive(Subcommand)]
+enum TotpCommand {
+    /// print the otpauth URI of a new secret, and the recovery codes once a code is right
+    Enroll,
+    /// stop asking for a code, a TOTP or recovery code is asked for
+    Disable,
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+
+    let result = match (&cli.socket, &cli.tls_ca) {
+        (Some(path), _) => match UnixStream::connect(path).await {
+            Ok(stream) => run(stream, &cli).await,
+            Err(e) => Err(e.into()),
+        },
+        // a daemon with a certificate only speaks TLS on its TCP address
+        (None, Some(ca_path)) => match connect_tls(&cli, ca_path).await {
+            Ok(stream) => run(stream, &cli).await,
+            Err(e) => Err(e.into()),
+        },
+        (None, None) => match TcpStream::connect(&cli.addr).await {
+            Ok(stream) => run(stream, &cli).await,
+            Err(e) => Err(e.into()),
+        },
+    };
+
+    if let Err(e) = result {
+        eprintln!("ysnp: {}", e);
+        std::process::exit(1);
+    }
+}
+
+async fn connect_tls(
+    cli: &Cli,
+    ca_path: &Path,
+) -> std::io::Result<tokio_rustls::client::TlsStream<TcpStream>> {
+    let client_cert = cli.tls_cert.as_deref().zip(cli.tls_key.as_deref());
+    let connector = tls::connector(ca_path, client_cert)?;
+    tls::connect(&cli.addr, &connector).await
+}
+
+async fn run<S>(stream: S, cli: &Cli) -> Result<(), Box<dyn Error>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut client = Client::new(stream).await?;
+
+    if let Command::Login = cli.command {
+        let password = rpassword::prompt_password("Password: ")?;
+        let session = match client.check_identity(&password).await {
+            // TOTP is enabled, ask for the code and try again
+            Err(ClientError::Server { code: 24, .. }) => {
+                let code = prompt_line("TOTP or recovery code: ")?;
+                client
+                    .check_identity_with_totp(&password, Some(&code))
+                    .await?
+            }
+            session => session?,
+        };
+        save_token(&session.token)?;
+        if cli.json {
+            println!("{}", json!(session));
+        } else {
+            println!("Logged in for {} seconds", session.expires_in);
+        }
+        return Ok(());
+    }
+
+    client.set_token(load_token());
+
+    match &cli.command {
+        Command::Login => unreachable!(),
+        Command::Logout => {
+            client.logout().await?;
+            let _ = std::fs::remove_file(token_path());
+        }
+        Command::Ls {
+            query,
+            limit,
+            offset,
+            sort,
+        } => {
+            let page = match query {
+                Some(query) => client.search(query, *limit, *offset).await?,
+                None => client.get_info(*limit, *offset, *sort).await?,
+            };
+            print_page(&page, cli.json);
+        }
+        Command::Show { id } => {
+            let password = client.get_password(*id).await?;
+            if cli.json {
+                println!("{}", json!({ "id": id, "password": password }));
+            } else {
+                println!("{}", password);
+            }
+        }
+        Command::Add {
+            account,
+            url,
+            name,
+            note,
+        } => {
+            let password = prompt_new_password()?;
+            client
+                .add_account(NewAccount {
+                    account: account.clone(),
+                    password,
+                    site_url: url.clone(),
+                    site_name: name.clone(),
+                    note: note.clone(),
+                })
+                .await?;
+        }
+        Command::Edit {
+            id,
+            account,
+            url,
+            name,
+            note,
+            clear_name,
+            clear_note,
+            password,
+        } => {
+            let password = if *password {
+                Some(prompt_new_password()?)
+            } else {
+                None
+            };
+            let clearable = |value: &Option<String>, clear: bool| {
+                if clear {
+                    Some(None)
+                } else {
+                    value.clone().map(Some)
+                }
+            };
+
+            client
+                .patch_account(
+                    *id,
+                    AccountPatch {
+                        account: account.clone(),
+                        password,
+                        site_url: url.clone(),
+                        site_name: clearable(name, *clear_name),
+                        note: clearable(note, *clear_note),
+                    },
+                )
+                .await?;
+        }
+        Command::Rm { id } => client.delete_account(*id).await?,
+        Command::Lock => client.lock().await?,
+        Command::Passwd => {
+            let old_password = rpassword::prompt_password("Current password: ")?;
+            let new_password = prompt_new_password()?;
+            client
+                .change_master_password(&old_password, &new_password)
+                .await?;
+        }
+        Command::Totp {
+            command: TotpCommand::Enroll,
+        } => {
+            let uri = client.enroll_totp().await?;
+            eprintln!("Add this to the authenticator app:\n{}", uri);
+            let code = prompt_line("Code: ")?;
+            let recovery_codes = client.confirm_totp(&code).await?;
+            if cli.json {
+                println!(
+                    "{}",
+                    json!({ "uri": uri, "recovery_codes": recovery_codes })
+                );
+            } else {
+                println!("Recovery codes, each works once if the app is lost:");
+                for code in recovery_codes {
+                    println!("{}", code);
+                }
+            }
+        }
+        Command::Totp {
+            command: TotpCommand::Disable,
+        } => {
+            let code = prompt_line("TOTP or recovery code: ")?;
+            client.disable_totp(&code).await?;
+        }
+        Command::Watch => {
+            client.subscribe().await?;
+            loop {
+                let event = client.next_event().await?;
+                if cli.json {
+                    println!("{}", json!(event));
+                    continue;
+                }
+                match event {
+                    Event::Added { id } | Event::Changed { id } | Event::Deleted { id } => {
+                        println!("{}\t{}", event.name(), id)
+                    }
+                    Event::DeadLink { id, dead_link } => {
+                        let status = if dead_link { "dead" } else { "ok" };
+                        println!("{}\t{}\t{}", event.name(), id, status)
+                    }
+                    Event::Lagged { missed } => println!("{}\t{}", event.name(), missed),
+                }
+            }
+        }
+        Command::CheckLinks => {
+            let list = client.check_dead_link().await?;
+            if cli.json {
+                println!("{}", json!(list));
+            } else {
+                let rows = list
+                    .iter()
+                    .map(|item| {
+                        let status = if item.dead_link { "dead" } else { "ok" };
+                        vec![item.id.to_string(), status.to_string()]
+                    })
+                    .collect();
+                print_table(&["ID", "STATUS"], rows);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// ask for one line on the terminal, a code is not secret once it is used
+fn prompt_line(prompt: &str) -> Result<String, Box<dyn Error>> {
+    eprint!("{}", prompt);
+    std::io::stderr().flush()?;
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line)?;
+    Ok(line.trim().to_string())
+}
+
+/// ask for a password twice, without echo
+fn prompt_new_password() -> Result<String, Box<dyn Error>> {
+    let password = rpassword::prompt_password("New password: ")?;
+    let again = rpassword::prompt_password("Repeat the password: ")?;
+    if password != again {
+        return Err("The passwords do not match".into());
+    }
+    Ok(password)
+}
+
+fn print_page(page: &Page, as_json: bool) {
+    if as_json {
+        println!("{}", json!(page));
+        return;
+    }
+
+    let rows = page
+        .accounts
+        .iter()
+        .map(|item| {
+            vec![
+                item.id.map(|id| id.to_string()).unwrap_or_default(),
+                item.account.clone(),
+                item.site_url.clone(),
+                item.site_name.clone().unwrap_or_default(),
+                item.note.clone().unwrap_or_default(),
+                if item.dead_link { "dead" } else { "" }.to_string(),
+            ]
+        })
+        .collect::<Vec<_>>();
+    let shown = rows.len();
+    print_table(&["ID", "ACCOUNT", "URL", "NAME", "NOTE", "LINK"], rows);
+    println!("({} of {})", shown, page.total);
+}
+
+/// print the rows as columns padded to the widest cell
+fn print_table(header: &[&str], rows: Vec<Vec<String>>) {
+    let mut widths: Vec<usize> = header.iter().map(|cell| cell.chars().count()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let header = header.iter().map(|cell| cell.to_string()).collect();
+    for row in std::iter::once(header).chain(rows) {
+        let line = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    }
+}
+
+/// where `login` keeps the session token
+fn token_path() -> PathBuf {
+    match std::env::var_os("YSNP_TOKEN_FILE") {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let home = std::env::var_os("HOME").unwrap_or_else(|| ".".into());
+            PathBuf::from(home).join(".ysnp_token")
+        }
+    }
+}
+
+fn load_token() -> Option<String> {
+    std::fs::read_to_string(token_path())
+        .ok()
+        .map(|token| token.trim().to_string())
+        .filter(|token| !token.is_empty())
+}
+
+fn save_token(token: &str) -> std::io::Result<()> {
+    // the token is as good as the password while it lasts
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(token_path())?;
+    // the mode only applies to a new file
+    file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    file.write_all(token.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+
+        let cli = Cli::try_parse_from(["ysnp", "--json", "ls", "baidu", "--limit", "5"]).unwrap();
+        assert!(cli.json);
+        match cli.command {
+            Command::Ls { query, limit, .. } => {
+                assert_eq!(query.as_deref(), Some("baidu"));
+                assert_eq!(limit, Some(5));
+            }
+            _ => panic!("Expected ls"),
+        }
+
+        let cli = Cli::try_parse_from(["ysnp", "ls", "--sort", "modified"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Ls {
+                sort: Some(SortKey::Modified),
+                ..
+            }
+        ));
+
+        assert!(Cli::try_parse_from(["ysnp", "ls", "--sort", "nope"]).is_err());
+        assert!(Cli::try_parse_from(["ysnp", "ls", "baidu", "--sort", "name"]).is_err());
+        assert!(Cli::try_parse_from(["ysnp", "edit", "1", "--note", "a", "--clear-note"]).is_err());
+        assert!(Cli::try_parse_from(["ysnp", "rm"]).is_err());
+
+        let cli = Cli::try_parse_from([
+            "ysnp",
+            "--tls-ca",
+            "ca.pem",
+            "--tls-cert",
+            "me.pem",
+            "--tls-key",
+            "me.key",
+            "lock",
+        ])
+        .unwrap();
+        assert_eq!(cli.tls_ca, Some(PathBuf::from("ca.pem")));
+        assert_eq!(cli.tls_key, Some(PathBuf::from("me.key")));
+        // a client certificate only makes sense over TLS, and TLS only over TCP
+        assert!(Cli::try_parse_from([
+            "ysnp",
+            "--tls-cert",
+            "me.pem",
+            "--tls-key",
+            "me.key",
+            "lock"
+        ])
+        .is_err());
+        assert!(Cli::try_parse_from([
+            "ysnp",
+            "--tls-ca",
+            "ca.pem",
+            "--tls-cert",
+            "me.pem",
+            "lock"
+        ])
+        .is_err());
+        assert!(
+            Cli::try_parse_from(["ysnp", "--socket", "s", "--tls-ca", "ca.pem", "lock"]).is_err()
+        );
+    }
+}
@@ -0,0 +1,573 @@
+use std::env;
+use std::error::Error;
+use std::path::Path;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, Cell, Clear, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::UnixStream;
+use you_should_not_pass::client::{AccountPatch, Client, ClientError, NewAccount};
+use you_should_not_pass::db::models::WebsiteAccountWithDeadLink;
+use you_should_not_pass::tls;
+
+const HELP: &str =
+    "q quit  / filter  enter reveal  a add  e edit  d delete  c check links  r reload";
+
+/// the fields of the add and edit form, in order
+const FORM_FIELDS: [&str; 5] = ["Account", "Password", "Site URL", "Site name", "Note"];
+const PASSWORD_FIELD: usize = 1;
+
+enum Mode {
+    /// `totp` is asked for once the daemon says TOTP is enabled
+    Login {
+        password: String,
+        totp: Option<String>,
+    },
+    List,
+    Filter,
+    Form(Form),
+    ConfirmDelete(i32),
+}
+
+/// The add form if `id` is `None`, the edit form otherwise
+struct Form {
+    id: Option<i32>,
+    fields: [String; 5],
+    focus: usize,
+}
+
+struct App {
+    mode: Mode,
+    accounts: Vec<WebsiteAccountWithDeadLink>,
+    filter: String,
+    table: TableState,
+    /// the password revealed on keypress, hidden again on the next one
+    revealed: Option<(i32, String)>,
+    status: String,
+    quit: bool,
+}
+
+/// full-screen client of the you_should_not_pass daemon
+///
+/// The daemon is reached at `YSNP_ADDR`, `127.0.0.1:6123` by default, or at the
+/// unix socket `YSNP_SOCKET`. With `YSNP_TLS_CA` the TCP address speaks TLS,
+/// presenting `YSNP_TLS_CERT` and `YSNP_TLS_KEY` if both are set, like `ysnp`.
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let addr = env::var("YSNP_ADDR").unwrap_or_else(|_| "127.0.0.1:6123".to_string());
+
+    match (env::var_os("YSNP_SOCKET"), env::var_os("YSNP_TLS_CA")) {
+        (Some(path), _) => start(Client::new(UnixStream::connect(path).await?).await?).await,
+        (None, Some(ca_path)) => {
+            let cert_path = env::var_os("YSNP_TLS_CERT");
+            let key_path = env::var_os("YSNP_TLS_KEY");
+            let client_cert = cert_path
+                .as_deref()
+                .map(Path::new)
+                .zip(key_path.as_deref().map(Path::new));
+            let connector = tls::connector(Path::new(&ca_path), client_cert)?;
+            let stream = tls::connect(&addr, &connector).await?;
+            start(Client::new(stream).await?).await
+        }
+        (None, None) => start(Client::connect(&addr).await?).await,
+    }
+}
+
+async fn start<S>(mut client: Client<S>) -> Result<(), Box<dyn Error>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut terminal = ratatui::init();
+    let result = run(&mut terminal, &mut client).await;
+    ratatui::restore();
+    result
+}
+
+async fn run<S>(
+    terminal: &mut DefaultTerminal,
+    client: &mut Client<S>,
+) -> Result<(), Box<dyn Error>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut app = App::new();
+
+    while !app.quit {
+        terminal.draw(|frame| draw(frame, &mut app))?;
+
+        // reading the terminal blocks, keep it off the runtime's workers
+        if let Event::Key(key) = tokio::task::spawn_blocking(event::read).await?? {
+            if key.kind == KeyEventKind::Press {
+                app.handle_key(key, client).await;
+            }
+        }
+    }
+    Ok(())
+}
+
+impl App {
+    fn new() -> Self {
+        App {
+            mode: Mode::Login {
+                password: String::new(),
+                totp: None,
+            },
+            accounts: Vec::new(),
+            filter: String::new(),
+            table: TableState::default(),
+            revealed: None,
+            status: "Enter the password to unlock".to_string(),
+            quit: false,
+        }
+    }
+
+    /// the accounts matching the filter
+    fn visible(&self) -> Vec<&WebsiteAccountWithDeadLink> {
+        self.accounts
+            .iter()
+            .filter(|item| matches_filter(item, &self.filter))
+            .collect()
+    }
+
+    fn selected(&self) -> Option<&WebsiteAccountWithDeadLink> {
+        self.table
+            .selected()
+            .and_then(|i| self.visible().get(i).copied())
+    }
+
+    async fn handle_key<S>(&mut self, key: KeyEvent, client: &mut Client<S>)
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        match &mut self.mode {
+            Mode::Login { password, totp } => match key.code {
+                KeyCode::Esc => self.quit = true,
+                KeyCode::Enter => {
+                    match client
+                        .check_identity_with_totp(password, totp.as_deref())
+                        .await
+                    {
+                        Ok(_) => {
+                            self.mode = Mode::List;
+                            self.reload(client).await;
+                        }
+                        Err(ClientError::Server { code: 24, .. }) if totp.is_none() => {
+                            *totp = Some(String::new());
+                            self.status = "Enter a TOTP or recovery code".to_string();
+                        }
+                        Err(e) => {
+                            password.clear();
+                            *totp = None;
+                            self.status = e.to_string();
+                        }
+                    }
+                }
+                KeyCode::Backspace => {
+                    totp.as_mut().unwrap_or(password).pop();
+                }
+                KeyCode::Char(c) => totp.as_mut().unwrap_or(password).push(c),
+                _ => {}
+            },
+            Mode::Filter => match key.code {
+                KeyCode::Esc | KeyCode::Enter => self.mode = Mode::List,
+                KeyCode::Backspace => {
+                    self.filter.pop();
+                    self.table.select_first();
+                }
+                KeyCode::Char(c) => {
+                    self.filter.push(c);
+                    self.table.select_first();
+                }
+                _ => {}
+            },
+            Mode::Form(form) => match key.code {
+                KeyCode::Esc => self.mode = Mode::List,
+                KeyCode::Enter => self.submit(client).await,
+                KeyCode::Tab | KeyCode::Down => form.focus = (form.focus + 1) % FORM_FIELDS.len(),
+                KeyCode::BackTab | KeyCode::Up => {
+                    form.focus = (form.focus + FORM_FIELDS.len() - 1) % FORM_FIELDS.len()
+                }
+                KeyCode::Backspace => {
+                    form.fields[form.focus].pop();
+                }
+                KeyCode::Char(c) => form.fields[form.focus].push(c),
+                _ => {}
+            },
+            Mode::ConfirmDelete(id) => {
+                let id = *id;
+                self.mode = Mode::List;
+                if key.code == KeyCode::Char('y') {
+                    match client.delete_account(id).await {
+                        Ok(()) => {
+                            self.status = format!("Deleted {}", id);
+                            self.reload(client).await;
+                        }
+                        Err(e) => self.status = e.to_string(),
+                    }
+                }
+            }
+            Mode::List => self.handle_list_key(key, client).await,
+        }
+    }
+
+    async fn handle_list_key<S>(&mut self, key: KeyEvent, client: &mut Client<S>)
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        // any key hides the password again
+        let revealed = self.revealed.take();
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Down | KeyCode::Char('j') => self.table.select_next(),
+            KeyCode::Up | KeyCode::Char('k') => self.table.select_previous(),
+            KeyCode::Char('/') => self.mode = Mode::Filter,
+            KeyCode::Char('r') => self.reload(client).await,
+            KeyCode::Enter => {
+                let Some(id) = self.selected().and_then(|item| item.id) else {
+                    return;
+                };
+                if revealed.is_some_and(|(revealed, _)| revealed == id) {
+                    return;
+                }
+                match client.get_password(id).await {
+                    Ok(password) => self.revealed = Some((id, password)),
+                    Err(e) => self.status = e.to_string(),
+                }
+            }
+            KeyCode::Char('a') => self.mode = Mode::Form(Form::add()),
+            KeyCode::Char('e') => {
+                if let Some(item) = self.selected() {
+                    self.mode = Mode::Form(Form::edit(item));
+                }
+            }
+            KeyCode::Char('d') => {
+                if let Some(id) = self.selected().and_then(|item| item.id) {
+                    self.mode = Mode::ConfirmDelete(id);
+                }
+            }
+            KeyCode::Char('c') => {
+                self.status = "Checking links...".to_string();
+                match client.check_dead_link().await {
+                    Ok(list) => {
+                        for link in &list {
+                            if let Some(item) =
+                                self.accounts.iter_mut().find(|i| i.id == Some(link.id))
+                            {
+                                item.dead_link = link.dead_link;
+                            }
+                        }
+                        let dead = list.iter().filter(|link| link.dead_link).count();
+                        self.status = format!("{} of {} links are dead", dead, list.len());
+                    }
+                    Err(e) => self.status = e.to_string(),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    async fn reload<S>(&mut self, client: &mut Client<S>)
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        match client.get_info(None, None, None).await {
+            Ok(page) => {
+                self.status = format!("{} website accounts", page.total);
+                self.accounts = page.accounts;
+                if self.table.selected().is_none() {
+                    self.table.select_first();
+                }
+            }
+            Err(e) => self.status = e.to_string(),
+        }
+    }
+
+    async fn submit<S>(&mut self, client: &mut Client<S>)
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let Mode::Form(form) = &self.mode else {
+            return;
+        };
+        let account = match form.to_account() {
+            Ok(account) => account,
+            Err(e) => {
+                self.status = e.to_string();
+                return;
+            }
+        };
+
+        let result = match form.id {
+            None => client.add_account(account).await,
+            Some(id) => client.patch_account(id, Form::to_patch(account)).await,
+        };
+
+        match result {
+            Ok(()) => {
+                self.status = "Saved".to_string();
+                self.mode = Mode::List;
+                self.reload(client).await;
+            }
+            Err(e) => self.status = e.to_string(),
+        }
+    }
+}
+
+impl Form {
+    fn add() -> Self {
+        Form {
+            id: None,
+            fields: Default::default(),
+            focus: 0,
+        }
+    }
+
+    /// the password is left empty, and kept if it stays so
+    fn edit(item: &WebsiteAccountWithDeadLink) -> Self {
+        Form {
+            id: item.id,
+            fields: [
+                item.account.clone(),
+                String::new(),
+                item.site_url.clone(),
+                item.site_name.clone().unwrap_or_default(),
+                item.note.clone().unwrap_or_default(),
+            ],
+            focus: 0,
+        }
+    }
+
+    /// an empty password keeps the current one,
+    /// an empty site name or note clears it
+    fn to_patch(account: NewAccount) -> AccountPatch {
+        AccountPatch {
+            account: Some(account.account),
+            password: Some(account.password).filter(|p| !p.is_empty()),
+            site_url: Some(account.site_url),
+            site_name: Some(account.site_name),
+            note: Some(account.note),
+        }
+    }
+
+    fn to_account(&self) -> Result<NewAccount, &'static str> {
+        let [account, password, site_url, site_name, note] = self.fields.clone();
+        if account.is_empty() || site_url.is_empty() {
+            return Err("Account and site URL are required");
+        }
+        if self.id.is_none() && password.is_empty() {
+            return Err("Password is required");
+        }
+
+        Ok(NewAccount {
+            account,
+            password,
+            site_url,
+            site_name: Some(site_name).filter(|s| !s.is_empty()),
+            note: Some(note).filter(|s| !s.is_empty()),
+        })
+    }
+}
+
+/// case insensitive match on the account, site name and site url
+fn matches_filter(item: &WebsiteAccountWithDeadLink, filter: &str) -> bool {
+    let filter = filter.to_lowercase();
+    [
+        Some(&item.account),
+        Some(&item.site_url),
+        item.site_name.as_ref(),
+    ]
+    .into_iter()
+    .flatten()
+    .any(|field| field.to_lowercase().contains(&filter))
+}
+
+fn draw(frame: &mut Frame, app: &mut App) {
+    let [list_area, status_area] =
+        Layout::vertical([Constraint::Min(1), Constraint::Length(3)]).areas(frame.area());
+
+    let rows: Vec<Row> = app
+        .visible()
+        .into_iter()
+        .map(|item| {
+            let password = match &app.revealed {
+                Some((id, password)) if item.id == Some(*id) => password.clone(),
+                _ => "••••••".to_string(),
+            };
+            let link = if item.dead_link {
+                Cell::from("dead").style(Style::default().fg(Color::Red))
+            } else {
+                Cell::from("ok").style(Style::default().fg(Color::Green))
+            };
+            Row::new(vec![
+                Cell::from(item.id.map(|id| id.to_string()).unwrap_or_default()),
+                Cell::from(item.site_name.clone().unwrap_or_default()),
+                Cell::from(item.account.clone()),
+                Cell::from(password),
+                Cell::from(item.site_url.clone()),
+                link,
+            ])
+        })
+        .collect();
+
+    let title = if app.filter.is_empty() {
+        " you_should_not_pass ".to_string()
+    } else {
+        format!(" you_should_not_pass, filter: {} ", app.filter)
+    };
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(5),
+            Constraint::Percentage(20),
+            Constraint::Percentage(20),
+            Constraint::Percentage(20),
+            Constraint::Fill(1),
+            Constraint::Length(5),
+        ],
+    )
+    .header(
+        Row::new(["ID", "NAME", "ACCOUNT", "PASSWORD", "URL", "LINK"])
+            .style(Style::default().add_modifier(Modifier::BOLD)),
+    )
+    .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+    .block(Block::default().borders(Borders::ALL).title(title));
+    frame.render_stateful_widget(table, list_area, &mut app.table);
+
+    let hint = match app.mode {
+        Mode::Filter => "type to filter  enter/esc done",
+        Mode::Form(_) => "tab next field  enter save  esc cancel",
+        Mode::ConfirmDelete(_) => "y delete  any other key cancel",
+        _ => HELP,
+    };
+    let status = Paragraph::new(vec![Line::from(app.status.as_str()), Line::from(hint)])
+        .block(Block::default().borders(Borders::TOP));
+    frame.render_widget(status, status_area);
+
+    match &app.mode {
+        Mode::Login { password, totp } => {
+            let area = popup(frame.area(), 40, 3);
+            frame.render_widget(Clear, area);
+            let input = match totp {
+                Some(code) => Paragraph::new(code.as_str())
+                    .block(Block::default().borders(Borders::ALL).title(" Code ")),
+                None => Paragraph::new("*".repeat(password.chars().count()))
+                    .block(Block::default().borders(Borders::ALL).title(" Password ")),
+            };
+            frame.render_widget(input, area);
+        }
+        Mode::Form(form) => draw_form(frame, form),
+        Mode::ConfirmDelete(id) => {
+            let area = popup(frame.area(), 40, 3);
+            frame.render_widget(Clear, area);
+            frame.render_widget(
+                Paragraph::new(format!("Delete website account {}? (y/n)", id))
+                    .block(Block::default().borders(Borders::ALL)),
+                area,
+            );
+        }
+        _ => {}
+    }
+}
+
+fn draw_form(frame: &mut Frame, form: &Form) {
+    let title = match form.id {
+        None => " Add website account ".to_string(),
+        Some(id) => format!(" Edit website account {} ", id),
+    };
+    let area = popup(frame.area(), 60, FORM_FIELDS.len() as u16 + 2);
+    frame.render_widget(Clear, area);
+
+    let lines: Vec<Line> = FORM_FIELDS
+        .iter()
+        .zip(&form.fields)
+        .enumerate()
+        .map(|(i, (name, value))| {
+            let value = if i == PASSWORD_FIELD {
+                "*".repeat(value.chars().count())
+            } else {
+                value.clone()
+            };
+            let line = Line::from(format!("{:>10}: {}", name, value));
+            if i == form.focus {
+                line.style(Style::default().add_modifier(Modifier::REVERSED))
+            } else {
+                line
+            }
+        })
+        .collect();
+
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(title)),
+        area,
+    );
+}
+
+/// a box of the given size in the middle of `area`
+fn popup(area: Rect, width: u16, height: u16) -> Rect {
+    let width = width.min(area.width);
+    let height = height.min(area.height);
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(id: i32, account: &str, site_url: &str) -> WebsiteAccountWithDeadLink {
+        WebsiteAccountWithDeadLink {
+            id: Some(id),
+            account: account.to_string(),
+            site_url: site_url.to_string(),
+            site_name: Some("Baidu".to_string()),
+            note: None,
+            updated_at: 0,
+            dead_link: false,
+        }
+    }
+
+    #[test]
+    fn test_matches_filter() {
+        let item = item(1, "me", "https://www.baidu.com");
+        assert!(matches_filter(&item, ""));
+        assert!(matches_filter(&item, "BAIDU"));
+        assert!(matches_filter(&item, "me"));
+        assert!(!matches_filter(&item, "google"));
+    }
+
+    #[test]
+    fn test_form() {
+        let mut form = Form::add();
+        assert!(form.to_account().is_err());
+
+        form.fields = [
+            "me".to_string(),
+            "secret".to_string(),
+            "https://www.baidu.com".to_string(),
+            String::new(),
+            String::new(),
+        ];
+        let account = form.to_account().unwrap();
+        assert_eq!(account.password, "secret");
+        assert_eq!(account.site_name, None);
+
+        // editing may leave the password empty
+        let form = Form::edit(&item(1, "me", "https://www.baidu.com"));
+        let account = form.to_account().unwrap();
+        assert_eq!(account.password, "");
+        assert_eq!(account.site_name.as_deref(), Some("Baidu"));
+
+        let patch = Form::to_patch(account);
+        assert_eq!(patch.password, None);
+        assert_eq!(patch.note, Some(None));
+    }
+}
@@ -0,0 +1,771 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::Write;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::write::EncoderWriter;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpStream, ToSocketAddrs};
+
+pub use crate::db::decode_field;
+pub use crate::db::event::Event;
+use crate::db::models::{AuditLog, Device, WebsiteAccountWithDeadLink};
+use crate::db::{BatchItem, SortKey};
+use crate::process::frame::{read_frame, write_frame};
+
+/// A typed client of the daemon
+///
+/// The connection is switched to the JSON protocol right away,
+/// see `process::json`. The fields of the website accounts are
+/// base64 encoded on the way in and decoded on the way out.
+///
+/// ```no_run
+/// # async fn run() -> Result<(), you_should_not_pass::client::ClientError> {
+/// use you_should_not_pass::client::Client;
+///
+/// let mut client = Client::connect("127.0.0.1:6123").await?;
+/// client.check_identity("my_password").await?;
+/// let page = client.get_info(Some(20), None, None).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Client<S> {
+    stream: S,
+    /// sent with every request, the session of the connection is used if `None`
+    token: Option<String>,
+    /// events pushed while waiting for a response, see `next_event`
+    events: VecDeque<Event>,
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    Io(std::io::Error),
+    /// the response is not what the protocol says
+    Protocol(String),
+    /// the daemon answered with an error, `code` is the response code
+    Server {
+        code: u8,
+        kind: String,
+        message: String,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Session {
+    pub token: String,
+    pub expires_in: u64,
+}
+
+/// one page of website accounts, `total` counts all of them
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Page {
+    pub total: usize,
+    pub accounts: Vec<WebsiteAccountWithDeadLink>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeadLink {
+    pub id: i32,
+    pub dead_link: bool,
+}
+
+/// one page of the audit log, `valid` is false if the hash chain is broken
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuditLogPage {
+    pub total: usize,
+    pub valid: bool,
+    pub entries: Vec<AuditLog>,
+}
+
+/// A website account to add, or the new content of one
+#[derive(Debug, Clone, Default)]
+pub struct NewAccount {
+    pub account: String,
+    pub password: String,
+    pub site_url: String,
+    pub site_name: Option<String>,
+    pub note: Option<String>,
+}
+
+/// The fields of a website account to change, `None` leaves a field as it is
+///
+/// `Some(None)` clears `site_name` or `note`.
+#[derive(Debug, Clone, Default)]
+pub struct AccountPatch {
+    pub account: Option<String>,
+    pub password: Option<String>,
+    pub site_url: Option<String>,
+    pub site_name: Option<Option<String>>,
+    pub note: Option<Option<String>>,
+}
+
+impl Client<TcpStream> {
+    pub async fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self, ClientError> {
+        let stream = TcpStream::connect(addr).await?;
+        Client::new(stream).await
+    }
+}
+
+impl<S> Client<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    /// say Hello over the tab separated protocol and switch to JSON
+    pub async fn new(mut stream: S) -> Result<Self, ClientError> {
+        write_frame(&mut stream, b"7\t2").await?;
+        match read_frame(&mut stream).await? {
+            Some(response) if response == b"0" => Ok(Client {
+                stream,
+                token: None,
+                events: VecDeque::new(),
+            }),
+            Some(response) => Err(ClientError::Server {
+                code: String::from_utf8_lossy(&response).parse().unwrap_or(0),
+                kind: "unsupported_version".to_string(),
+                message: "Protocol version 2 is not supported".to_string(),
+            }),
+            None => Err(ClientError::Protocol("Connection closed".to_string())),
+        }
+    }
+
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+
+    /// use a token from an earlier connection
+    pub fn set_token(&mut self, token: Option<String>) {
+        self.token = token;
+    }
+
+    pub async fn check_identity(&mut self, password: &str) -> Result<Session, ClientError> {
+        self.check_identity_with_totp(password, None).await
+    }
+
+    /// `totp` is a TOTP or recovery code, once TOTP is enabled the daemon
+    /// answers a `totp_required` error (24) without it
+    pub async fn check_identity_with_totp(
+        &mut self,
+        password: &str,
+        totp: Option<&str>,
+    ) -> Result<Session, ClientError> {
+        let session: Session = self
+            .request(json!({ "action": "check_identity", "password": password, "totp": totp }))
+            .await?;
+        self.token = Some(session.token.clone());
+        Ok(session)
+    }
+
+    /// the connection is closed by the daemon afterwards
+    pub async fn logout(mut self) -> Result<(), ClientError> {
+        self.request::<Value>(json!({ "action": "logout" }))
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn get_info(
+        &mut self,
+        limit: Option<usize>,
+        offset: Option<usize>,
+        sort: Option<SortKey>,
+    ) -> Result<Page, ClientError> {
+        let page = self
+            .request(json!({
+                "action": "get_info",
+                "limit": limit,
+                "offset": offset,
+                "sort": sort,
+            }))
+            .await?;
+        Ok(decode_page(page))
+    }
+
+    pub async fn search(
+        &mut self,
+        query: &str,
+        limit: Option<usize>,
+        offset: Option<usize>,
+    ) -> Result<Page, ClientError> {
+        let page = self
+            .request(json!({
+                "action": "search_website_account",
+                "query": query,
+                "limit": limit,
+                "offset": offset,
+            }))
+            .await?;
+        Ok(decode_page(page))
+    }
+
+    pub async fn get_password(&mut self, website_id: i32) -> Result<String, ClientError> {
+        #[derive(Deserialize)]
+        struct Password {
+            password: String,
+        }
+
+        let data: Password = self
+            .request(json!({
+                "action": "get_website_account_password",
+                "website_id": website_id,
+            }))
+            .await?;
+        Ok(decode_field(&data.password))
+    }
+
+    pub async fn add_account(&mut self, account: NewAccount) -> Result<(), ClientError> {
+        self.ack(json!({
+            "action": "add_website_account",
+            "account": encode_field(&account.account),
+            "password": encode_field(&account.password),
+            "site_url": encode_field(&account.site_url),
+            "site_name": account.site_name.as_deref().map(encode_field),
+            "note": account.note.as_deref().map(encode_field),
+        }))
+        .await
+    }
+
+    pub async fn change_account(
+        &mut self,
+        id: i32,
+        account: NewAccount,
+    ) -> Result<(), ClientError> {
+        self.ack(json!({
+            "action": "change_website_account",
+            "id": id,
+            "new_account": encode_field(&account.account),
+            "new_password": encode_field(&account.password),
+            "new_site_url": encode_field(&account.site_url),
+            "new_site_name": account.site_name.as_deref().map(encode_field),
+            "new_note": account.note.as_deref().map(encode_field),
+        }))
+        .await
+    }
+
+    /// change only the fields given in `patch`, the password is not needed for that
+    pub async fn patch_account(&mut self, id: i32, patch: AccountPatch) -> Result<(), ClientError> {
+        let mut request = json!({ "action": "patch_website_account", "id": id });
+        let fields = [
+            ("account", patch.account.map(Some)),
+            ("password", patch.password.map(Some)),
+            ("site_url", patch.site_url.map(Some)),
+            ("site_name", patch.site_name),
+            ("note", patch.note),
+        ];
+        for (name, value) in fields {
+            // a missing field is kept, `null` is cleared
+            if let Some(value) = value {
+                request[name] = json!(value.as_deref().map(encode_field));
+            }
+        }
+        self.ack(request).await
+    }
+
+    pub async fn delete_account(&mut self, website_id: i32) -> Result<(), ClientError> {
+        self.ack(json!({ "action": "delete_website_account", "website_id": website_id }))
+            .await
+    }
+
+    /// run the items in one transaction, nothing is changed if one fails,
+    /// return the id of the website account of every item
+    pub async fn batch(&mut self, items: Vec<BatchItem>) -> Result<Vec<i32>, ClientError> {
+        let items: Vec<BatchItem> = items.into_iter().map(encode_batch_item).collect();
+        self.request(json!({ "action": "batch", "actions": items }))
+            .await
+    }
+
+    pub async fn check_dead_link(&mut self) -> Result<Vec<DeadLink>, ClientError> {
+        self.request(json!({ "action": "check_dead_link" })).await
+    }
+
+    /// enroll the TLS client certificate the stream was made with
+    pub async fn enroll_device(&mut self, label: &str) -> Result<(), ClientError> {
+        self.ack(json!({ "action": "enroll_device", "label": label }))
+            .await
+    }
+
+    pub async fn list_devices(&mut self) -> Result<Vec<Device>, ClientError> {
+        self.request(json!({ "action": "list_devices" })).await
+    }
+
+    pub async fn revoke_device(&mut self, device_id: i32) -> Result<(), ClientError> {
+        self.ack(json!({ "action": "revoke_device", "device_id": device_id }))
+            .await
+    }
+
+    /// get the changes of the website accounts from now on, see `next_event`
+    pub async fn subscribe(&mut self) -> Result<(), ClientError> {
+        self.ack(json!({ "action": "subscribe" })).await
+    }
+
+    /// wait for the next change, after `subscribe`
+    pub async fn next_event(&mut self) -> Result<Event, ClientError> {
+        if let Some(event) = self.events.pop_front() {
+            return Ok(event);
+        }
+
+        let response = self.read_response().await?;
+        match Self::event_of(&response) {
+            Some(event) => Ok(event?),
+            None => Err(ClientError::Protocol("Expected an event".to_string())),
+        }
+    }
+
+    pub async fn get_audit_log(
+        &mut self,
+        limit: Option<usize>,
+        offset: Option<usize>,
+    ) -> Result<AuditLogPage, ClientError> {
+        self.request(json!({ "action": "get_audit_log", "limit": limit, "offset": offset }))
+            .await
+    }
+
+    /// wipe the key of the passwords on the daemon until the next `check_identity`
+    pub async fn lock(&mut self) -> Result<(), ClientError> {
+        self.ack(json!({ "action": "lock" })).await
+    }
+
+    /// only for the `master_password` backend of the daemon
+    pub async fn change_master_password(
+        &mut self,
+        old_password: &str,
+        new_password: &str,
+    ) -> Result<(), ClientError> {
+        self.ack(json!({
+            "action": "change_master_password",
+            "old_password": old_password,
+            "new_password": new_password,
+        }))
+        .await
+    }
+
+    /// the otpauth URI of a new secret, it is required once `confirm_totp` succeeds
+    pub async fn enroll_totp(&mut self) -> Result<String, ClientError> {
+        #[derive(Deserialize)]
+        struct TotpUri {
+            uri: String,
+        }
+
+        let data: TotpUri = self.request(json!({ "action": "enroll_totp" })).await?;
+        Ok(data.uri)
+    }
+
+    /// return the recovery codes, they are not shown again
+    pub async fn confirm_totp(&mut self, code: &str) -> Result<Vec<String>, ClientError> {
+        #[derive(Deserialize)]
+        struct RecoveryCodes {
+            recovery_codes: Vec<String>,
+        }
+
+        let data: RecoveryCodes = self
+            .request(json!({ "action": "confirm_totp", "code": code }))
+            .await?;
+        Ok(data.recovery_codes)
+    }
+
+    /// `code` is a TOTP or recovery code
+    pub async fn disable_totp(&mut self, code: &str) -> Result<(), ClientError> {
+        self.ack(json!({ "action": "disable_totp", "code": code }))
+            .await
+    }
+
+    async fn ack(&mut self, request: Value) -> Result<(), ClientError> {
+        self.request::<Value>(request).await.map(|_| ())
+    }
+
+    /// send one request and return the `data` of the response
+    async fn request<T: DeserializeOwned>(&mut self, mut request: Value) -> Result<T, ClientError> {
+        if let Some(token) = &self.token {
+            request["token"] = json!(token);
+        }
+
+        write_frame(&mut self.stream, request.to_string().as_bytes()).await?;
+
+        // events may come before the response
+        let mut response = loop {
+            let response = self.read_response().await?;
+            match Self::event_of(&response) {
+                Some(event) => self.events.push_back(event?),
+                None => break response,
+            }
+        };
+
+        if response["ok"] == json!(true) {
+            return Ok(serde_json::from_value(response["data"].take())?);
+        }
+
+        let field = |name: &str| response["error"][name].as_str().unwrap_or("").to_string();
+        Err(ClientError::Server {
+            code: response["code"].as_u64().unwrap_or(0) as u8,
+            kind: field("kind"),
+            message: field("message"),
+        })
+    }
+
+    async fn read_response(&mut self) -> Result<Value, ClientError> {
+        match read_frame(&mut self.stream).await? {
+            Some(response) => Ok(serde_json::from_slice(&response)?),
+            None => Err(ClientError::Protocol("Connection closed".to_string())),
+        }
+    }
+
+    /// the event of a pushed response, `None` for a response to a request
+    fn event_of(response: &Value) -> Option<Result<Event, ClientError>> {
+        if response["ok"] != json!(true) || response["code"] != json!(15) {
+            return None;
+        }
+        Some(serde_json::from_value(response["data"].clone()).map_err(Into::into))
+    }
+}
+
+/// base64 encode a field, the way the daemon expects it
+pub fn encode_field(data: &str) -> String {
+    let mut encoder = EncoderWriter::new(Vec::new(), &STANDARD);
+    encoder
+        .write_all(data.as_bytes())
+        .expect("Failed to write to memory");
+    let encoded = encoder.finish().expect("Failed to write to memory");
+    String::from_utf8(encoded).expect("base64 is always utf8")
+}
+
+fn encode_batch_item(item: BatchItem) -> BatchItem {
+    let encode = |data: Option<String>| data.as_deref().map(encode_field);
+    match item {
+        BatchItem::AddWebsiteAccount {
+            account,
+            password,
+            site_url,
+            site_name,
+            note,
+        } => BatchItem::AddWebsiteAccount {
+            account: encode_field(&account),
+            password: encode_field(&password),
+            site_url: encode_field(&site_url),
+            site_name: encode(site_name),
+            note: encode(note),
+        },
+        BatchItem::ChangeWebsiteAccount {
+            id,
+            new_account,
+            new_password,
+            new_site_name,
+            new_site_url,
+            new_note,
+        } => BatchItem::ChangeWebsiteAccount {
+            id,
+            new_account: encode_field(&new_account),
+            new_password: encode_field(&new_password),
+            new_site_name: encode(new_site_name),
+            new_site_url: encode_field(&new_site_url),
+            new_note: encode(new_note),
+        },
+        item @ BatchItem::DeleteWebsiteAccount { .. } => item,
+    }
+}
+
+fn decode_page(mut page: Page) -> Page {
+    for item in &mut page.accounts {
+        item.account = decode_field(&item.account);
+        item.site_url = decode_field(&item.site_url);
+        item.site_name = item.site_name.as_deref().map(decode_field);
+        item.note = item.note.as_deref().map(decode_field);
+    }
+    page
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Io(e) => write!(f, "Connection error: {}", e),
+            ClientError::Protocol(e) => write!(f, "Protocol error: {}", e),
+            ClientError::Server { code, message, .. } => write!(f, "{} ({})", message, code),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<std::io::Error> for ClientError {
+    fn from(e: std::io::Error) -> Self {
+        ClientError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ClientError {
+    fn from(e: serde_json::Error) -> Self {
+        ClientError::Protocol(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::db::{Audit, Db};
+    use crate::encrypt::DataKey;
+    use crate::process::auth::MockAuthenticator;
+    use crate::process::connection::Peer;
+    use crate::process::{process, Shared};
+    use crate::totp;
+
+    #[test]
+    fn test_encode_field() {
+        assert_eq!(encode_field("baidu"), "YmFpZHU=");
+        assert_eq!(decode_field(&encode_field("my\tnote")), "my\tnote");
+    }
+
+    #[tokio::test]
+    async fn test_subscribe() {
+        // an enrolled device needs no session
+        let db = Arc::new(Db::in_memory("test_client_subscribe"));
+        db.set_data_key(Some(DataKey::generate()));
+        let audit = Audit {
+            peer: "127.0.0.1".to_string(),
+            action: "test".to_string(),
+        };
+        let fingerprint = "subscribe_fingerprint".to_string();
+        if db
+            .add_device(&audit, "test_subscribe".to_string(), fingerprint.clone())
+            .await
+            .is_err()
+        {
+            panic!("Failed to add device");
+        }
+
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (_shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+        let peer = Peer {
+            addr: "127.0.0.1".to_string(),
+            cert_fingerprint: Some(fingerprint),
+        };
+        tokio::spawn(process(
+            server,
+            peer,
+            Shared::new(db.clone(), Arc::new(MockAuthenticator::new("secret")), None),
+            shutdown_rx,
+        ));
+
+        let mut client = Client::new(client).await.unwrap();
+        client.subscribe().await.unwrap();
+
+        // a change made elsewhere is pushed
+        let website_id = db
+            .add_new_website_account(
+                &audit,
+                encode_field("subscribe_account"),
+                encode_field("subscribe_password"),
+                encode_field("www.baidu.com"),
+                None,
+                None,
+            )
+            .await
+            .unwrap_or_else(|_| panic!("Failed to add new website account"));
+        assert_eq!(
+            client.next_event().await.unwrap(),
+            Event::Added { id: website_id }
+        );
+
+        // the event of our own change is pushed after the response
+        client.delete_account(website_id).await.unwrap();
+        assert_eq!(
+            client.next_event().await.unwrap(),
+            Event::Deleted { id: website_id }
+        );
+
+        // a lock ends the subscription, it stays ended once the vault is unlocked again
+        let website_id = db
+            .add_new_website_account(
+                &audit,
+                encode_field("subscribe_account"),
+                encode_field("subscribe_password"),
+                encode_field("www.baidu.com"),
+                None,
+                None,
+            )
+            .await
+            .unwrap_or_else(|_| panic!("Failed to add new website account"));
+        assert_eq!(
+            client.next_event().await.unwrap(),
+            Event::Added { id: website_id }
+        );
+        db.lock();
+        if db.delete_website_account(&audit, website_id).await.is_err() {
+            panic!("Failed to delete website account");
+        }
+        let quiet = Duration::from_millis(100);
+        assert!(tokio::time::timeout(quiet, client.next_event())
+            .await
+            .is_err());
+
+        db.set_data_key(Some(DataKey::generate()));
+        let website_id = db
+            .add_new_website_account(
+                &audit,
+                encode_field("subscribe_account"),
+                encode_field("subscribe_password"),
+                encode_field("www.baidu.com"),
+                None,
+                None,
+            )
+            .await
+            .unwrap_or_else(|_| panic!("Failed to add new website account"));
+        assert!(tokio::time::timeout(quiet, client.next_event())
+            .await
+            .is_err());
+
+        // a subscription is refused while locked, it has to be renewed after the unlock
+        db.lock();
+        match client.subscribe().await {
+            Err(ClientError::Server { kind, .. }) => assert_eq!(kind, "locked"),
+            _ => panic!("Subscribe should be refused while locked"),
+        }
+        db.set_data_key(Some(DataKey::generate()));
+        client.subscribe().await.unwrap();
+        client.delete_account(website_id).await.unwrap();
+        assert_eq!(
+            client.next_event().await.unwrap(),
+            Event::Deleted { id: website_id }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_client() {
+        let db = Arc::new(Db::in_memory("test_client"));
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (_shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+        tokio::spawn(process(
+            server,
+            Peer::default(),
+            Shared::new(db, Arc::new(MockAuthenticator::new("secret")), None),
+            shutdown_rx,
+        ));
+
+        let mut client = Client::new(client).await.unwrap();
+        match client.get_info(None, None, None).await {
+            Err(ClientError::Server { code, kind, .. }) => {
+                assert_eq!(code, 6);
+                assert_eq!(kind, "unauthenticated");
+            }
+            _ => panic!("GetInfo without a session should fail"),
+        }
+
+        client.set_token(Some("no_such_token".to_string()));
+        assert!(client.list_devices().await.is_err());
+
+        match client.check_identity("wrong").await {
+            Err(ClientError::Server { code, kind, .. }) => {
+                assert_eq!(code, 3);
+                assert_eq!(kind, "identity_error");
+            }
+            _ => panic!("A wrong password should be refused"),
+        }
+        client.check_identity("secret").await.unwrap();
+        assert!(client.list_devices().await.is_ok());
+
+        let account = NewAccount {
+            account: "client_account".to_string(),
+            password: "client\tpassword".to_string(),
+            site_url: "www.baidu.com".to_string(),
+            site_name: Some("baidu".to_string()),
+            note: None,
+        };
+        client.add_account(account.clone()).await.unwrap();
+        let page = client.get_info(None, None, None).await.unwrap();
+        assert_eq!(page.total, 1);
+        assert_eq!(page.accounts[0].account, account.account);
+        assert_eq!(page.accounts[0].site_name, account.site_name);
+        let id = page.accounts[0].id.unwrap();
+        assert_eq!(client.get_password(id).await.unwrap(), account.password);
+
+        // nothing can be encrypted once the vault is locked
+        client.lock().await.unwrap();
+        let account = NewAccount {
+            account: "locked_account".to_string(),
+            password: "locked_password".to_string(),
+            site_url: "www.baidu.com".to_string(),
+            ..Default::default()
+        };
+        match client.add_account(account).await {
+            Err(ClientError::Server { code, kind, .. }) => {
+                assert_eq!(code, 23);
+                assert_eq!(kind, "locked");
+            }
+            _ => panic!("Adding to a locked vault should fail"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_totp() {
+        let db = Arc::new(Db::in_memory("test_client_totp"));
+        let shared = Shared::new(db.clone(), Arc::new(MockAuthenticator::new("secret")), None);
+        let (_shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+        let connect = || async {
+            let (client, server) = tokio::io::duplex(64 * 1024);
+            let shutdown = shutdown_rx.clone();
+            tokio::spawn(process(server, Peer::default(), shared.clone(), shutdown));
+            Client::new(client).await.unwrap()
+        };
+        let refused = |result: Result<Session, ClientError>| match result {
+            Err(ClientError::Server { code, .. }) => code,
+            Ok(_) => panic!("CheckIdentity should be refused"),
+            Err(e) => panic!("{}", e),
+        };
+
+        let mut client = connect().await;
+        client.check_identity("secret").await.unwrap();
+        let uri = client.enroll_totp().await.unwrap();
+        let secret = uri
+            .split_once("secret=")
+            .and_then(|(_, rest)| rest.split('&').next())
+            .and_then(totp::from_base32)
+            .unwrap();
+        let code = totp::code_at(&secret, totp::step_of(crate::db::now() as u64));
+        let recovery_codes = client.confirm_totp(&code).await.unwrap();
+
+        // Without a code the password is not checked, so the prompt for the code
+        // uses up no attempt and says nothing about the password
+        let failures = || async {
+            db.get_auth_failure(&Peer::default().addr)
+                .await
+                .unwrap()
+                .map(|failure| failure.failures)
+        };
+        let mut client = connect().await;
+        assert_eq!(refused(client.check_identity("wrong").await), 24);
+        assert_eq!(refused(client.check_identity("secret").await), 24);
+        assert_eq!(failures().await, None);
+        client
+            .check_identity_with_totp("secret", Some(&recovery_codes[2]))
+            .await
+            .unwrap();
+        assert_eq!(failures().await, None);
+
+        let mut client = connect().await;
+        // a used code can't be replayed
+        assert_eq!(
+            refused(client.check_identity_with_totp("secret", Some(&code)).await),
+            3
+        );
+        assert_eq!(
+            refused(
+                client
+                    .check_identity_with_totp("wrong", Some(&recovery_codes[0]))
+                    .await
+            ),
+            3
+        );
+        assert_eq!(failures().await, Some(2));
+        client
+            .check_identity_with_totp("secret", Some(&recovery_codes[0]))
+            .await
+            .unwrap();
+        assert_eq!(failures().await, None);
+
+        client.disable_totp(&recovery_codes[1]).await.unwrap();
+        let mut client = connect().await;
+        client.check_identity("secret").await.unwrap();
+    }
+}
@@ -0,0 +1,129 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::encrypt;
+
+/// Where the daemon listens when `LISTEN_ADDR` is not set
+pub const DEFAULT_LISTEN_ADDR: &str = "127.0.0.1:6123";
+/// How long running requests get to finish on shutdown when `SHUTDOWN_TIMEOUT` is not set
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long the vault stays unlocked without being used when `AUTO_LOCK_TIMEOUT` is not set
+pub const DEFAULT_AUTO_LOCK_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
+/// Settings of the daemon, read from the environment (or `.env`)
+///
+/// - `DATABASE_URL`: the sqlite database, required
+/// - `KEY`: the legacy data key, 32 bytes in base64, checked here and read again on unlock
+/// - `LISTEN_ADDR`: TCP address to listen on
+/// - `UNIX_SOCKET_PATH`: also listen on this unix socket
+/// - `TLS_CERT_PATH`, `TLS_KEY_PATH`: serve TLS on `LISTEN_ADDR` with this PEM certificate and key
+/// - `TLS_CLIENT_AUTH`: set to `true` to ask clients for a certificate,
+///   enrolled certificates authenticate their device
+/// - `SHUTDOWN_TIMEOUT`: seconds to wait for running requests on SIGTERM/SIGINT
+/// - `AUTO_LOCK_TIMEOUT`: seconds without a request before the vault locks itself, `0` never
+/// - `AUTH_BACKEND`: how CheckIdentity checks the password, see `AuthConfig`
+pub struct Config {
+    pub database_url: String,
+    pub listen_addr: String,
+    pub unix_socket_path: Option<PathBuf>,
+    pub tls: Option<TlsConfig>,
+    pub shutdown_timeout: Duration,
+    /// `None` never locks the vault by itself
+    pub auto_lock_timeout: Option<Duration>,
+    pub auth: AuthConfig,
+}
+
+pub struct TlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    pub client_auth: bool,
+}
+
+/// Backend of CheckIdentity, chosen by `AUTH_BACKEND`
+///
+/// - `pam` (default): the password of `PAM_USER` (default the current user)
+///   checked by the PAM service `PAM_SERVICE` (default `login`)
+/// - `master_password`: a password of the vault alone, kept as an Argon2id hash in the database.
+///   It is set on the first run to `MASTER_PASSWORD_HASH` (an Argon2 PHC string) if given,
+///   otherwise it is asked for on the terminal
+/// - `mock`: accepts `MOCK_PASSWORD`, only for tests and only built with the `mock-auth` feature
+pub enum AuthConfig {
+    Pam {
+        service: String,
+        user: String,
+    },
+    MasterPassword {
+        initial_hash: Option<String>,
+    },
+    #[cfg(feature = "mock-auth")]
+    Mock {
+        password: String,
+    },
+}
+
+impl AuthConfig {
+    fn from_env() -> Self {
+        match std::env::var("AUTH_BACKEND").as_deref() {
+            Ok("pam") | Err(_) => AuthConfig::Pam {
+                service: std::env::var("PAM_SERVICE").unwrap_or_else(|_| "login".to_string()),
+                user: std::env::var("PAM_USER").unwrap_or_else(|_| whoami::username()),
+            },
+            Ok("master_password") => AuthConfig::MasterPassword {
+                initial_hash: std::env::var("MASTER_PASSWORD_HASH").ok(),
+            },
+            #[cfg(feature = "mock-auth")]
+            Ok("mock") => AuthConfig::Mock {
+                password: std::env::var("MOCK_PASSWORD")
+                    .expect("MOCK_PASSWORD must be set for the mock backend"),
+            },
+            #[cfg(not(feature = "mock-auth"))]
+            Ok("mock") => panic!("AUTH_BACKEND=mock needs a build with the mock-auth feature"),
+            Ok(backend) => panic!("Unknown AUTH_BACKEND: {}", backend),
+        }
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        dotenv::dotenv().ok();
+
+        if encrypt::legacy_key().is_err() {
+            panic!("KEY must be 32 bytes in base64");
+        }
+
+        Config {
+            database_url: std::env::var("DATABASE_URL").expect("DATABASE_URL must be set"),
+            listen_addr: std::env::var("LISTEN_ADDR")
+                .unwrap_or_else(|_| DEFAULT_LISTEN_ADDR.to_string()),
+            unix_socket_path: std::env::var_os("UNIX_SOCKET_PATH").map(PathBuf::from),
+            tls: match (
+                std::env::var_os("TLS_CERT_PATH"),
+                std::env::var_os("TLS_KEY_PATH"),
+            ) {
+                (Some(cert_path), Some(key_path)) => Some(TlsConfig {
+                    cert_path: cert_path.into(),
+                    key_path: key_path.into(),
+                    client_auth: std::env::var("TLS_CLIENT_AUTH").is_ok_and(|v| v == "true"),
+                }),
+                (None, None) => None,
+                _ => panic!("TLS_CERT_PATH and TLS_KEY_PATH must be set together"),
+            },
+            shutdown_timeout: match std::env::var("SHUTDOWN_TIMEOUT") {
+                Ok(secs) => Duration::from_secs(
+                    secs.parse()
+                        .expect("SHUTDOWN_TIMEOUT must be a number of seconds"),
+                ),
+                Err(_) => DEFAULT_SHUTDOWN_TIMEOUT,
+            },
+            auto_lock_timeout: match std::env::var("AUTO_LOCK_TIMEOUT") {
+                Ok(secs) => Some(Duration::from_secs(
+                    secs.parse()
+                        .expect("AUTO_LOCK_TIMEOUT must be a number of seconds"),
+                ))
+                .filter(|timeout| !timeout.is_zero()),
+                Err(_) => Some(DEFAULT_AUTO_LOCK_TIMEOUT),
+            },
+            auth: AuthConfig::from_env(),
+        }
+    }
+}
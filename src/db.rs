@@ -1,16 +1,66 @@
+mod error;
+pub mod event;
 pub mod models;
 mod schema;
 
+use std::collections::HashMap;
+use std::io::Read;
+use std::sync::{Mutex, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::read::DecoderReader;
+use diesel::connection::SimpleConnection;
+use diesel::dsl::sql;
 use diesel::prelude::*;
-use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+use diesel::r2d2::{ConnectionManager, CustomizeConnection, Pool, PooledConnection};
 use diesel::result::Error;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::broadcast;
 
-use crate::encrypt::{decrypt, encrypt};
+use crate::encrypt::{self, decrypt, encrypt, DataKey};
+use crate::totp;
+pub use error::DbError;
+use event::{Event, EVENT_CAPACITY};
 
 type SqlitePool = PooledConnection<ConnectionManager<SqliteConnection>>;
 
+/// How a list of website accounts is sorted
+#[derive(Debug, PartialEq, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    #[default]
+    Id,
+    /// by site name, the ones without a name last
+    Name,
+    Url,
+    /// most recently changed first
+    Modified,
+}
+
+impl std::str::FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "id" => Ok(SortKey::Id),
+            "name" => Ok(SortKey::Name),
+            "url" => Ok(SortKey::Url),
+            "modified" => Ok(SortKey::Modified),
+            _ => Err(format!("Invalid sort key: {}", s)),
+        }
+    }
+}
+
 pub struct Db {
     conn: Pool<ConnectionManager<SqliteConnection>>,
+    /// every change of the website accounts is sent here, see `subscribe`
+    events: broadcast::Sender<Event>,
+    /// the last known dead link status of every website account
+    dead_links: Mutex<HashMap<i32, bool>>,
+    /// the key of the passwords, only ever kept in memory
+    data_key: RwLock<Option<DataKey>>,
 }
 
 // Connection
@@ -18,16 +68,95 @@ impl Db {
     pub fn new(url: &str) -> Self {
         let manager = ConnectionManager::<SqliteConnection>::new(url);
         let pool = Pool::builder()
+            .connection_customizer(Box::new(ConnectionOptions))
             .build(manager)
             .expect("Failed to create pool");
-        Db { conn: pool }
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        Db {
+            conn: pool,
+            events,
+            dead_links: Mutex::new(HashMap::new()),
+            data_key: RwLock::new(None),
+        }
     }
 
-    fn get_conn(&self) -> Result<SqlitePool, Error> {
-        if let Ok(conn) = self.conn.get() {
-            Ok(conn)
-        } else {
-            Err(Error::NotFound)
+    /// a database of its own in memory with every migration run,
+    /// for the tests which change the state of the whole vault
+    #[cfg(test)]
+    pub(crate) fn in_memory(name: &str) -> Self {
+        Db::migrated(&format!("file:{}?mode=memory&cache=shared", name))
+    }
+
+    /// a new database file in the temp directory with every migration run,
+    /// for the tests which write from more than one connection at once
+    #[cfg(test)]
+    pub(crate) fn in_temp_file(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("{}_{}.db", name, std::process::id()));
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+        Db::migrated(path.to_str().expect("The temp directory is not utf8"))
+    }
+
+    #[cfg(test)]
+    fn migrated(url: &str) -> Self {
+        let db = Db::new(url);
+        let mut migrations: Vec<_> = std::fs::read_dir("migrations")
+            .expect("Failed to read the migrations")
+            .map(|entry| entry.unwrap().path().join("up.sql"))
+            .filter(|up| up.exists())
+            .collect();
+        migrations.sort();
+
+        let mut conn = db.get_conn().unwrap();
+        for up in migrations {
+            conn.batch_execute(&std::fs::read_to_string(up).unwrap())
+                .expect("Failed to run a migration");
+        }
+        db
+    }
+
+    fn get_conn(&self) -> Result<SqlitePool, DbError> {
+        self.conn
+            .get()
+            .map_err(|e| DbError::PoolUnavailable(e.to_string()))
+    }
+}
+
+/// Every connection waits for a lock instead of failing right away,
+/// and readers don't block the writer with WAL
+#[derive(Debug)]
+struct ConnectionOptions;
+
+impl CustomizeConnection<SqliteConnection, diesel::r2d2::Error> for ConnectionOptions {
+    fn on_acquire(&self, conn: &mut SqliteConnection) -> Result<(), diesel::r2d2::Error> {
+        conn.batch_execute("PRAGMA busy_timeout = 5000; PRAGMA journal_mode = WAL;")
+            .map_err(diesel::r2d2::Error::QueryError)
+    }
+}
+
+// Events
+impl Db {
+    /// receive the changes of the website accounts from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    fn notify(&self, event: Event) {
+        // nobody listening is fine
+        let _ = self.events.send(event);
+    }
+
+    /// remember the dead link status of the website accounts,
+    /// and notify about the ones which changed since the last check
+    pub fn update_dead_links(&self, list: &[(i32, bool)]) {
+        let mut dead_links = self.dead_links.lock().unwrap();
+        for &(id, dead_link) in list {
+            if let Some(last) = dead_links.insert(id, dead_link) {
+                if last != dead_link {
+                    self.notify(Event::DeadLink { id, dead_link });
+                }
+            }
         }
     }
 }
@@ -36,15 +165,14 @@ impl Db {
 impl Db {
     pub async fn add_new_website_account(
         &self,
+        audit: &Audit,
         new_account: String,
         new_password: String,
         new_site_url: String,
         new_site_name: Option<String>,
         new_note: Option<String>,
-    ) -> Result<(), diesel::result::Error> {
-        use schema::website_account::dsl::*;
-
-        match encrypt(new_password).await {
+    ) -> Result<i32, DbError> {
+        match encrypt(&self.data_key()?, new_password).await {
             Ok(new_password) => {
                 let mut conn = self.get_conn()?;
                 let new_website_account = models::WebsiteAccount {
@@ -54,62 +182,134 @@ impl Db {
                     site_url: new_site_url,
                     site_name: new_site_name,
                     note: new_note,
+                    updated_at: now(),
                 };
 
-                diesel::insert_into(website_account)
-                    .values(&new_website_account)
-                    .execute(&mut conn)?;
-                Ok(())
+                let new_id = conn.immediate_transaction(|conn| {
+                    let new_id = insert_website_account(conn, new_website_account)?;
+                    append_audit_log(conn, audit, Some(new_id), true)?;
+                    Ok::<_, Error>(new_id)
+                })?;
+                self.notify(Event::Added { id: new_id });
+                Ok(new_id)
             }
-            Err(_) => Err(Error::NotFound),
+            Err(_) => Err(DbError::Crypto),
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn update_website_account(
         &self,
+        audit: &Audit,
         website_id: i32,
         new_account: String,
         new_password: String,
         new_site_name: Option<String>,
         new_site_url: String,
         new_note: Option<String>,
-    ) -> Result<(), diesel::result::Error> {
-        use schema::website_account::dsl::*;
-
-        let new_password = if let Ok(new_password) = encrypt(new_password).await {
+    ) -> Result<(), DbError> {
+        let new_password = if let Ok(new_password) = encrypt(&self.data_key()?, new_password).await
+        {
             new_password
         } else {
-            return Err(Error::NotFound);
+            return Err(DbError::Crypto);
         };
 
         let mut conn = self.get_conn()?;
-        diesel::update(website_account.filter(id.eq(website_id)))
-            .set((
-                account.eq(new_account),
-                password.eq(new_password),
-                site_name.eq(new_site_name),
-                site_url.eq(new_site_url),
-                note.eq(new_note),
-            ))
-            .execute(&mut conn)?;
+        conn.immediate_transaction(|conn| {
+            let changed = set_website_account(
+                conn,
+                website_id,
+                new_account,
+                new_password,
+                new_site_name,
+                new_site_url,
+                new_note,
+            )?;
+            if changed == 0 {
+                return Err(DbError::NotFound);
+            }
+            append_audit_log(conn, audit, Some(website_id), true)?;
+            Ok(())
+        })?;
+        self.notify(Event::Changed { id: website_id });
+        Ok(())
+    }
+
+    /// change only the given fields of a website account,
+    /// `Some(None)` clears `new_site_name` or `new_note`
+    ///
+    /// Fails with `NotFound` if there is no such website account.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn patch_website_account(
+        &self,
+        audit: &Audit,
+        website_id: i32,
+        new_account: Option<String>,
+        new_password: Option<String>,
+        new_site_url: Option<String>,
+        new_site_name: Option<Option<String>>,
+        new_note: Option<Option<String>>,
+    ) -> Result<(), DbError> {
+        use schema::website_account::dsl::*;
+
+        let new_password = match new_password {
+            Some(new_password) => match encrypt(&self.data_key()?, new_password).await {
+                Ok(new_password) => Some(new_password),
+                Err(_) => return Err(DbError::Crypto),
+            },
+            None => None,
+        };
+
+        let patch = models::WebsiteAccountPatch {
+            account: new_account,
+            password: new_password,
+            site_url: new_site_url,
+            site_name: new_site_name,
+            note: new_note,
+            updated_at: now(),
+        };
+
+        let mut conn = self.get_conn()?;
+        conn.immediate_transaction(|conn| {
+            let changed = diesel::update(website_account.filter(id.eq(website_id)))
+                .set(&patch)
+                .execute(conn)?;
+            if changed == 0 {
+                return Err(DbError::NotFound);
+            }
+            append_audit_log(conn, audit, Some(website_id), true)?;
+            Ok(())
+        })?;
+        self.notify(Event::Changed { id: website_id });
         Ok(())
     }
 
     pub async fn delete_website_account(
         &self,
+        audit: &Audit,
         website_id: i32,
-    ) -> Result<(), diesel::result::Error> {
+    ) -> Result<(), DbError> {
         use schema::website_account::dsl::*;
 
         let mut conn = self.get_conn()?;
-        diesel::delete(website_account.filter(id.eq(website_id))).execute(&mut conn)?;
+        conn.immediate_transaction(|conn| {
+            let deleted =
+                diesel::delete(website_account.filter(id.eq(website_id))).execute(conn)?;
+            if deleted == 0 {
+                return Err(DbError::NotFound);
+            }
+            append_audit_log(conn, audit, Some(website_id), true)?;
+            Ok(())
+        })?;
+        self.notify(Event::Deleted { id: website_id });
         Ok(())
     }
 
     pub async fn get_website_account_password(
         &self,
         website_id: i32,
-    ) -> Result<Option<String>, diesel::result::Error> {
+    ) -> Result<Option<String>, DbError> {
         use schema::website_account::dsl::*;
 
         let mut conn = self.get_conn()?;
@@ -119,33 +319,67 @@ impl Db {
             .first::<String>(&mut conn)
             .optional()?;
 
-        let searched_password = if let Ok(result) = decrypt(result.expect("NULL")).await {
+        let result = match result {
+            Some(result) => result,
+            None => return Ok(None),
+        };
+
+        let searched_password = if let Ok(result) = decrypt(&self.data_key()?, result).await {
             result
         } else {
-            return Err(Error::NotFound);
+            return Err(DbError::Crypto);
         };
 
         Ok(Some(searched_password))
     }
 
-    pub async fn get_all_website_account(
-        &self,
-    ) -> Result<Vec<models::WebsiteAccount>, diesel::result::Error> {
+    /// the passwords are left encrypted,
+    /// use `get_website_account_password` to reveal one of them
+    pub async fn get_all_website_account(&self) -> Result<Vec<models::WebsiteAccount>, DbError> {
         use schema::website_account::dsl::*;
 
         let mut conn = self.get_conn()?;
-        let mut results = website_account.load::<models::WebsiteAccount>(&mut conn)?;
-
-        for result in results.iter_mut() {
-            if let Ok(de_password) = decrypt(result.password.clone()).await {
-                result.password = de_password;
-            }
-        }
+        let results = website_account.load::<models::WebsiteAccount>(&mut conn)?;
 
         Ok(results)
     }
 
-    pub async fn get_all_id_and_url(&self) -> Result<Vec<(String, i32)>, diesel::result::Error> {
+    /// one page of the website accounts, and the number of all website accounts
+    pub async fn get_website_account_page(
+        &self,
+        sort: SortKey,
+        limit: Option<usize>,
+        offset: Option<usize>,
+    ) -> Result<(usize, Vec<models::WebsiteAccount>), DbError> {
+        let results = self.get_all_website_account().await?;
+
+        Ok(paginate(results, sort, limit, offset))
+    }
+
+    /// search the website accounts by account, site name, site url and note
+    ///
+    /// A website account matches if the query is part of one of those fields (ignoring case),
+    /// or if the query is a domain and the site url is on it or on one of its subdomains.
+    ///
+    /// return the number of all matches besides the requested page
+    pub async fn search_website_account(
+        &self,
+        query: &str,
+        limit: Option<usize>,
+        offset: Option<usize>,
+    ) -> Result<(usize, Vec<models::WebsiteAccount>), DbError> {
+        let results = self.get_all_website_account().await?;
+
+        let query = query.to_lowercase();
+        let results = results
+            .into_iter()
+            .filter(|item| matches_query(item, &query))
+            .collect();
+
+        Ok(paginate(results, SortKey::Id, limit, offset))
+    }
+
+    pub async fn get_all_id_and_url(&self) -> Result<Vec<(String, i32)>, DbError> {
         use schema::website_account::dsl::*;
 
         let mut conn = self.get_conn()?;
@@ -165,7 +399,7 @@ impl Db {
     pub async fn get_website_id_by_account(
         &self,
         account_to_search: &str,
-    ) -> Result<Option<i32>, diesel::result::Error> {
+    ) -> Result<Option<i32>, DbError> {
         use schema::website_account::dsl::*;
 
         let mut conn = self.get_conn()?;
@@ -180,9 +414,874 @@ impl Db {
     }
 }
 
+/// insert a website account, the password has to be encrypted already,
+/// return the id of it
+fn insert_website_account(
+    conn: &mut SqliteConnection,
+    new_website_account: models::WebsiteAccount,
+) -> Result<i32, diesel::result::Error> {
+    use schema::website_account::dsl::*;
+
+    diesel::insert_into(website_account)
+        .values(&new_website_account)
+        .execute(conn)?;
+    diesel::select(sql::<diesel::sql_types::Integer>("last_insert_rowid()")).get_result(conn)
+}
+
+/// change a website account, the password has to be encrypted already,
+/// return the number of changed rows
+fn set_website_account(
+    conn: &mut SqliteConnection,
+    website_id: i32,
+    new_account: String,
+    new_password: String,
+    new_site_name: Option<String>,
+    new_site_url: String,
+    new_note: Option<String>,
+) -> Result<usize, diesel::result::Error> {
+    use schema::website_account::dsl::*;
+
+    diesel::update(website_account.filter(id.eq(website_id)))
+        .set((
+            account.eq(new_account),
+            password.eq(new_password),
+            site_name.eq(new_site_name),
+            site_url.eq(new_site_url),
+            note.eq(new_note),
+            updated_at.eq(now()),
+        ))
+        .execute(conn)
+}
+
+/// One change of `Db::batch`, with the same fields as the single actions
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum BatchItem {
+    AddWebsiteAccount {
+        account: String,
+        password: String,
+        site_url: String,
+        site_name: Option<String>,
+        note: Option<String>,
+    },
+    ChangeWebsiteAccount {
+        id: i32,
+        new_account: String,
+        new_password: String,
+        new_site_name: Option<String>,
+        new_site_url: String,
+        new_note: Option<String>,
+    },
+    DeleteWebsiteAccount {
+        website_id: i32,
+    },
+}
+
+impl BatchItem {
+    /// name of the item, same as the single action
+    pub fn name(&self) -> &'static str {
+        match self {
+            BatchItem::AddWebsiteAccount { .. } => "add_website_account",
+            BatchItem::ChangeWebsiteAccount { .. } => "change_website_account",
+            BatchItem::DeleteWebsiteAccount { .. } => "delete_website_account",
+        }
+    }
+}
+
+/// Why a batch was rolled back
+///
+/// `index` is the item which failed, `None` if the batch failed as a whole.
+#[derive(Debug)]
+pub struct BatchError {
+    pub index: Option<usize>,
+    pub error: DbError,
+}
+
+impl From<DbError> for BatchError {
+    fn from(error: DbError) -> Self {
+        BatchError { index: None, error }
+    }
+}
+
+impl From<diesel::result::Error> for BatchError {
+    fn from(error: diesel::result::Error) -> Self {
+        BatchError {
+            index: None,
+            error: error.into(),
+        }
+    }
+}
+
+// Batch
+impl Db {
+    /// run every item in one transaction,
+    /// return the id of the website account of every item
+    ///
+    /// If one item fails nothing is changed at all.
+    /// Changing or deleting a website account which does not exist fails too.
+    ///
+    /// Every item gets its entry of the audit log, and the batch itself last,
+    /// in the same transaction.
+    pub async fn batch(
+        &self,
+        audit: &Audit,
+        items: Vec<BatchItem>,
+    ) -> Result<Vec<i32>, BatchError> {
+        // The transaction can't wait, so encrypt the passwords first
+        let data_key = self.data_key()?;
+        let mut encrypted = Vec::with_capacity(items.len());
+        for (index, mut item) in items.into_iter().enumerate() {
+            let plain = match &mut item {
+                BatchItem::AddWebsiteAccount { password, .. } => Some(password),
+                BatchItem::ChangeWebsiteAccount { new_password, .. } => Some(new_password),
+                BatchItem::DeleteWebsiteAccount { .. } => None,
+            };
+            if let Some(plain) = plain {
+                *plain = encrypt(&data_key, std::mem::take(plain))
+                    .await
+                    .map_err(|_| BatchError {
+                        index: Some(index),
+                        error: DbError::Crypto,
+                    })?;
+            }
+            encrypted.push(item);
+        }
+
+        let mut conn = self.get_conn()?;
+        let results = conn.immediate_transaction(|conn| {
+            let mut results = Vec::with_capacity(encrypted.len());
+            for (index, item) in encrypted.into_iter().enumerate() {
+                let item_audit = Audit {
+                    peer: audit.peer.clone(),
+                    action: item.name().to_string(),
+                };
+                let event: fn(i32) -> Event = match item {
+                    BatchItem::AddWebsiteAccount { .. } => |id| Event::Added { id },
+                    BatchItem::ChangeWebsiteAccount { .. } => |id| Event::Changed { id },
+                    BatchItem::DeleteWebsiteAccount { .. } => |id| Event::Deleted { id },
+                };
+                let id = run_batch_item(conn, item)
+                    .and_then(|id| append_audit_log(conn, &item_audit, Some(id), true).map(|_| id))
+                    .map_err(|error| BatchError {
+                        index: Some(index),
+                        error: error.into(),
+                    })?;
+                results.push((id, event(id)));
+            }
+            append_audit_log(conn, audit, None, true)?;
+            Ok::<_, BatchError>(results)
+        })?;
+
+        // only notify once everything is committed
+        let mut ids = Vec::with_capacity(results.len());
+        for (id, event) in results {
+            self.notify(event);
+            ids.push(id);
+        }
+        Ok(ids)
+    }
+}
+
+fn run_batch_item(
+    conn: &mut SqliteConnection,
+    item: BatchItem,
+) -> Result<i32, diesel::result::Error> {
+    use schema::website_account::dsl::*;
+
+    match item {
+        BatchItem::AddWebsiteAccount {
+            account: new_account,
+            password: new_password,
+            site_url: new_site_url,
+            site_name: new_site_name,
+            note: new_note,
+        } => insert_website_account(
+            conn,
+            models::WebsiteAccount {
+                id: None,
+                account: new_account,
+                password: new_password,
+                site_url: new_site_url,
+                site_name: new_site_name,
+                note: new_note,
+                updated_at: now(),
+            },
+        ),
+        BatchItem::ChangeWebsiteAccount {
+            id: website_id,
+            new_account,
+            new_password,
+            new_site_name,
+            new_site_url,
+            new_note,
+        } => {
+            let changed = set_website_account(
+                conn,
+                website_id,
+                new_account,
+                new_password,
+                new_site_name,
+                new_site_url,
+                new_note,
+            )?;
+            if changed == 0 {
+                return Err(Error::NotFound);
+            }
+            Ok(website_id)
+        }
+        BatchItem::DeleteWebsiteAccount { website_id } => {
+            let deleted =
+                diesel::delete(website_account.filter(id.eq(website_id))).execute(conn)?;
+            if deleted == 0 {
+                return Err(Error::NotFound);
+            }
+            Ok(website_id)
+        }
+    }
+}
+
+// Device
+impl Db {
+    pub async fn add_device(
+        &self,
+        audit: &Audit,
+        new_label: String,
+        new_fingerprint: String,
+    ) -> Result<(), DbError> {
+        use schema::device::dsl::*;
+
+        let mut conn = self.get_conn()?;
+        let new_device = models::Device {
+            id: None,
+            label: new_label,
+            fingerprint: new_fingerprint,
+            revoked: false,
+        };
+
+        conn.immediate_transaction(|conn| {
+            diesel::insert_into(device)
+                .values(&new_device)
+                .execute(conn)?;
+            append_audit_log(conn, audit, None, true)
+        })?;
+        Ok(())
+    }
+
+    pub async fn get_device_by_fingerprint(
+        &self,
+        fingerprint_to_search: &str,
+    ) -> Result<Option<models::Device>, DbError> {
+        use schema::device::dsl::*;
+
+        let mut conn = self.get_conn()?;
+        let result = device
+            .filter(fingerprint.eq(fingerprint_to_search))
+            .first::<models::Device>(&mut conn)
+            .optional()?;
+
+        Ok(result)
+    }
+
+    pub async fn get_all_device(&self) -> Result<Vec<models::Device>, DbError> {
+        use schema::device::dsl::*;
+
+        let mut conn = self.get_conn()?;
+        let result = device.load::<models::Device>(&mut conn)?;
+
+        Ok(result)
+    }
+
+    pub async fn revoke_device(&self, audit: &Audit, device_id: i32) -> Result<(), DbError> {
+        use schema::device::dsl::*;
+
+        let mut conn = self.get_conn()?;
+        conn.immediate_transaction(|conn| {
+            let revoked_devices = diesel::update(device.filter(id.eq(device_id)))
+                .set(revoked.eq(true))
+                .execute(conn)?;
+            if revoked_devices == 0 {
+                return Err(DbError::NotFound);
+            }
+            append_audit_log(conn, audit, Some(device_id), true)?;
+            Ok(())
+        })
+    }
+}
+
+/// unix time in seconds
+pub(crate) fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+/// sort the website accounts and cut out one page,
+/// return the number of all website accounts as well
+///
+/// Sorting happens here rather than in SQL since the names and urls are base64 encoded.
+fn paginate(
+    mut results: Vec<models::WebsiteAccount>,
+    sort: SortKey,
+    limit: Option<usize>,
+    offset: Option<usize>,
+) -> (usize, Vec<models::WebsiteAccount>) {
+    match sort {
+        SortKey::Id => results.sort_by_key(|item| item.id),
+        SortKey::Name => results.sort_by_cached_key(|item| {
+            let name = item.site_name.as_deref().map(decode_field);
+            (
+                name.is_none(),
+                name.map(|name| name.to_lowercase()),
+                item.id,
+            )
+        }),
+        SortKey::Url => results
+            .sort_by_cached_key(|item| (decode_field(&item.site_url).to_lowercase(), item.id)),
+        SortKey::Modified => {
+            results.sort_by_key(|item| (std::cmp::Reverse(item.updated_at), item.id))
+        }
+    }
+
+    let total = results.len();
+    let page = results
+        .into_iter()
+        .skip(offset.unwrap_or(0))
+        .take(limit.unwrap_or(usize::MAX))
+        .collect();
+
+    (total, page)
+}
+
+// Auth failure
+impl Db {
+    pub async fn get_auth_failure(
+        &self,
+        peer_to_search: &str,
+    ) -> Result<Option<models::AuthFailure>, DbError> {
+        use schema::auth_failure::dsl::*;
+
+        let mut conn = self.get_conn()?;
+        let result = auth_failure
+            .filter(peer.eq(peer_to_search))
+            .first::<models::AuthFailure>(&mut conn)
+            .optional()?;
+
+        Ok(result)
+    }
+
+    pub async fn set_auth_failure(&self, failure: models::AuthFailure) -> Result<(), DbError> {
+        use schema::auth_failure::dsl::*;
+
+        let mut conn = self.get_conn()?;
+        diesel::insert_into(auth_failure)
+            .values(&failure)
+            .on_conflict(peer)
+            .do_update()
+            .set(&failure)
+            .execute(&mut conn)?;
+        Ok(())
+    }
+
+    pub async fn delete_auth_failure(&self, peer_to_delete: &str) -> Result<(), DbError> {
+        use schema::auth_failure::dsl::*;
+
+        let mut conn = self.get_conn()?;
+        diesel::delete(auth_failure.filter(peer.eq(peer_to_delete))).execute(&mut conn)?;
+        Ok(())
+    }
+}
+
+/// id of the one row of the vault table
+const VAULT_ID: i32 = 1;
+
+// Master password
+impl Db {
+    /// the Argon2 hash of the master password, `None` before it is set
+    pub async fn get_master_password_hash(&self) -> Result<Option<String>, DbError> {
+        use schema::vault::dsl::*;
+
+        let mut conn = self.get_conn()?;
+        let result = vault
+            .filter(id.eq(VAULT_ID))
+            .select(password_hash)
+            .first::<String>(&mut conn)
+            .optional()?;
+
+        Ok(result)
+    }
+
+    /// set the master password on the first run, see `change_master_password` afterwards
+    pub async fn set_master_password_hash(&self, new_hash: String) -> Result<(), DbError> {
+        use schema::vault::dsl::*;
+
+        let mut conn = self.get_conn()?;
+        let row = models::Vault {
+            id: VAULT_ID,
+            password_hash: new_hash,
+            key_salt: None,
+            wrapped_key: None,
+        };
+        diesel::insert_into(vault).values(&row).execute(&mut conn)?;
+        Ok(())
+    }
+
+    /// store the hash of a new master password and wrap the data key for it,
+    /// the data key itself stays the same
+    pub async fn change_master_password(
+        &self,
+        audit: &Audit,
+        new_hash: String,
+        new_password: String,
+    ) -> Result<(), DbError> {
+        use schema::vault::dsl::*;
+
+        let data_key = self.data_key()?;
+        let salt = encrypt::generate_salt();
+        let kek = derive_key_blocking(new_password, salt.clone()).await?;
+        let wrapped = encrypt::wrap_key(&kek, &data_key).map_err(|_| DbError::Crypto)?;
+
+        let mut conn = self.get_conn()?;
+        conn.immediate_transaction(|conn| {
+            let changed = diesel::update(vault.filter(id.eq(VAULT_ID)))
+                .set((
+                    password_hash.eq(new_hash),
+                    key_salt.eq(Some(salt)),
+                    wrapped_key.eq(Some(wrapped)),
+                ))
+                .execute(conn)?;
+            if changed == 0 {
+                return Err(DbError::NotFound);
+            }
+            append_audit_log(conn, audit, None, true)?;
+            Ok(())
+        })
+    }
+}
+
+// Data key
+impl Db {
+    /// use this key for the passwords from now on, `None` forgets it
+    pub fn set_data_key(&self, key: Option<DataKey>) {
+        *self.data_key.write().unwrap_or_else(|e| e.into_inner()) = key;
+    }
+
+    /// wipe the data key from memory, nothing can be encrypted or decrypted afterwards
+    pub fn lock(&self) {
+        self.set_data_key(None);
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.data_key
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .is_none()
+    }
+
+    /// the key of the passwords, fails while it is not in memory
+    fn data_key(&self) -> Result<DataKey, DbError> {
+        self.data_key
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+            .ok_or(DbError::Locked)
+    }
+
+    /// unwrap the data key with the key derived from the master password,
+    /// and keep it in memory
+    pub async fn unlock(&self, password: String) -> Result<(), DbError> {
+        let data_key = self.unwrap_data_key(password).await?;
+        self.set_data_key(Some(data_key));
+        Ok(())
+    }
+
+    /// the data key, unwrapped with the key derived from the master password
+    ///
+    /// The first time it wraps the legacy `KEY` if there is one, or a new random key.
+    pub async fn unwrap_data_key(&self, password: String) -> Result<DataKey, DbError> {
+        use schema::vault::dsl::*;
+
+        let mut conn = self.get_conn()?;
+        let stored = vault
+            .filter(id.eq(VAULT_ID))
+            .select((key_salt, wrapped_key))
+            .first::<(Option<String>, Option<String>)>(&mut conn)?;
+
+        if let (Some(salt), Some(wrapped)) = stored {
+            let kek = derive_key_blocking(password, salt).await?;
+            return encrypt::unwrap_key(&kek, &wrapped).map_err(|_| DbError::Crypto);
+        }
+
+        let legacy_key = encrypt::legacy_key().map_err(|_| DbError::Crypto)?;
+        let data_key = self.first_data_key(legacy_key)?;
+        let salt = encrypt::generate_salt();
+        let kek = derive_key_blocking(password.clone(), salt.clone()).await?;
+        let wrapped = encrypt::wrap_key(&kek, &data_key).map_err(|_| DbError::Crypto)?;
+
+        // Only one first unlock may store its key, the others unwrap that one
+        let stored = diesel::update(vault.filter(id.eq(VAULT_ID)).filter(wrapped_key.is_null()))
+            .set((key_salt.eq(Some(salt)), wrapped_key.eq(Some(wrapped))))
+            .execute(&mut conn)?;
+        if stored == 0 {
+            return Box::pin(self.unwrap_data_key(password)).await;
+        }
+
+        Ok(data_key)
+    }
+
+    /// the data key to wrap on the first unlock, the legacy `KEY` or a new random key
+    ///
+    /// A new key is only made while no password is stored,
+    /// the stored ones could never be decrypted again otherwise.
+    fn first_data_key(&self, legacy_key: Option<DataKey>) -> Result<DataKey, DbError> {
+        use schema::website_account::dsl::*;
+
+        if let Some(key) = legacy_key {
+            return Ok(key);
+        }
+
+        let mut conn = self.get_conn()?;
+        let stored: i64 = website_account.count().get_result(&mut conn)?;
+        if stored > 0 {
+            return Err(DbError::KeyMissing);
+        }
+        Ok(DataKey::generate())
+    }
+}
+
+/// Argon2 takes a while, so it runs on a blocking thread
+async fn derive_key_blocking(password: String, salt: String) -> Result<DataKey, DbError> {
+    tokio::task::spawn_blocking(move || encrypt::derive_key(&password, &salt))
+        .await
+        .map_err(|_| DbError::Crypto)?
+        .map_err(|_| DbError::Crypto)
+}
+
+/// id of the one row of the totp table
+const TOTP_ID: i32 = 1;
+
+// TOTP
+impl Db {
+    /// whether CheckIdentity needs a code, only once the enrollment is confirmed
+    pub async fn totp_enabled(&self) -> Result<bool, DbError> {
+        use schema::totp::dsl::*;
+
+        let mut conn = self.get_conn()?;
+        let result = schema::totp::table
+            .filter(id.eq(TOTP_ID))
+            .select(confirmed)
+            .first::<bool>(&mut conn)
+            .optional()?;
+
+        Ok(result.unwrap_or(false))
+    }
+
+    /// store a new secret encrypted with the data key and return it,
+    /// it is required once `confirm_totp` accepted a code of it
+    pub async fn enroll_totp(&self, audit: &Audit) -> Result<Vec<u8>, DbError> {
+        let data_key = self.data_key()?;
+        let new_secret = totp::generate_secret();
+        let encrypted = encrypt(&data_key, totp::to_base32(&new_secret))
+            .await
+            .map_err(|_| DbError::Crypto)?;
+
+        let mut conn = self.get_conn()?;
+        let row = models::Totp {
+            id: TOTP_ID,
+            secret: encrypted,
+            confirmed: false,
+            last_step: None,
+        };
+        conn.immediate_transaction(|conn| {
+            diesel::replace_into(schema::totp::table)
+                .values(&row)
+                .execute(conn)?;
+            append_audit_log(conn, audit, None, true)
+        })?;
+        Ok(new_secret)
+    }
+
+    /// check a code of the new secret and require a code from now on,
+    /// return new recovery codes, `None` if the code is wrong
+    pub async fn confirm_totp(
+        &self,
+        audit: &Audit,
+        code: &str,
+    ) -> Result<Option<Vec<String>>, DbError> {
+        use schema::recovery_code::dsl::*;
+        use schema::totp::dsl::{confirmed, id};
+
+        let data_key = self.data_key()?;
+        if !totp::is_totp_code(code) || !self.verify_totp(&data_key, code).await? {
+            return Ok(None);
+        }
+
+        let codes = totp::generate_recovery_codes();
+        let hashes: Vec<_> = codes
+            .iter()
+            .map(|code| code_hash.eq(totp::hash_recovery_code(code)))
+            .collect();
+
+        let mut conn = self.get_conn()?;
+        conn.immediate_transaction(|conn| {
+            diesel::update(schema::totp::table.filter(id.eq(TOTP_ID)))
+                .set(confirmed.eq(true))
+                .execute(conn)?;
+            diesel::delete(recovery_code).execute(conn)?;
+            diesel::insert_into(recovery_code)
+                .values(&hashes)
+                .execute(conn)?;
+            append_audit_log(conn, audit, None, true)
+        })?;
+        Ok(Some(codes))
+    }
+
+    /// check a TOTP code, or a recovery code which is used up by it
+    ///
+    /// The key is given since CheckIdentity checks the code
+    /// before the data key is kept in memory.
+    pub async fn verify_totp(&self, data_key: &DataKey, code: &str) -> Result<bool, DbError> {
+        use schema::totp::dsl::{id, last_step};
+
+        let mut conn = self.get_conn()?;
+        let Some(row) = schema::totp::table
+            .filter(id.eq(TOTP_ID))
+            .first::<models::Totp>(&mut conn)
+            .optional()?
+        else {
+            return Ok(false);
+        };
+
+        if !totp::is_totp_code(code) {
+            if !row.confirmed {
+                return Ok(false);
+            }
+            use schema::recovery_code::dsl::*;
+            let used =
+                diesel::delete(recovery_code.filter(code_hash.eq(totp::hash_recovery_code(code))))
+                    .execute(&mut conn)?;
+            return Ok(used == 1);
+        }
+
+        let decrypted = decrypt(data_key, row.secret)
+            .await
+            .map_err(|_| DbError::Crypto)?;
+        let stored_secret = totp::from_base32(&decrypted).ok_or(DbError::Crypto)?;
+        let last = row.last_step.map(|step| step as u64);
+        let Some(step) = totp::verify(&stored_secret, code, now() as u64, last) else {
+            return Ok(false);
+        };
+
+        // Only one request can use the step, the code can't be replayed
+        let step = step as i64;
+        let used = diesel::update(
+            schema::totp::table
+                .filter(id.eq(TOTP_ID))
+                .filter(last_step.is_null().or(last_step.lt(step))),
+        )
+        .set(last_step.eq(Some(step)))
+        .execute(&mut conn)?;
+        Ok(used == 1)
+    }
+
+    /// stop requiring a code, `false` if `code` is wrong
+    pub async fn disable_totp(&self, audit: &Audit, code: &str) -> Result<bool, DbError> {
+        let data_key = self.data_key()?;
+        if !self.verify_totp(&data_key, code).await? {
+            return Ok(false);
+        }
+
+        let mut conn = self.get_conn()?;
+        conn.immediate_transaction(|conn| {
+            diesel::delete(schema::recovery_code::table).execute(conn)?;
+            diesel::delete(schema::totp::table).execute(conn)?;
+            append_audit_log(conn, audit, None, true)
+        })?;
+        Ok(true)
+    }
+}
+
+/// hash of the first entry's `prev_hash`
+const AUDIT_LOG_GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Who changes the vault
+///
+/// The methods which change the vault write their entry of the audit log
+/// in the same transaction, so no change is committed without it.
+#[derive(Debug, Clone)]
+pub struct Audit {
+    pub peer: String,
+    /// name of the action, like `delete_website_account`
+    pub action: String,
+}
+
+// Audit log
+impl Db {
+    /// append an entry to the audit log, chained to the last one
+    pub async fn add_audit_log(
+        &self,
+        new_peer: String,
+        new_action: String,
+        new_entry_id: Option<i32>,
+        new_success: bool,
+    ) -> Result<(), DbError> {
+        let audit = Audit {
+            peer: new_peer,
+            action: new_action,
+        };
+
+        let mut conn = self.get_conn()?;
+        conn.immediate_transaction(|conn| {
+            append_audit_log(conn, &audit, new_entry_id, new_success)
+        })?;
+        Ok(())
+    }
+
+    /// one page of the audit log, newest first,
+    /// return the number of all entries and whether the chain is intact as well
+    pub async fn get_audit_log(
+        &self,
+        limit: Option<usize>,
+        offset: Option<usize>,
+    ) -> Result<(usize, bool, Vec<models::AuditLog>), DbError> {
+        use schema::audit_log::dsl::*;
+
+        let mut conn = self.get_conn()?;
+        let results = audit_log
+            .order(id.asc())
+            .select(models::AuditLog::as_select())
+            .load(&mut conn)?;
+
+        let total = results.len();
+        let valid = verify_audit_log(&results);
+        let page = results
+            .into_iter()
+            .rev()
+            .skip(offset.unwrap_or(0))
+            .take(limit.unwrap_or(usize::MAX))
+            .collect();
+
+        Ok((total, valid, page))
+    }
+}
+
+/// append an entry to the audit log, chained to the last one
+///
+/// Only in an immediate transaction, so two entries can't be chained to the same one.
+fn append_audit_log(
+    conn: &mut SqliteConnection,
+    audit: &Audit,
+    new_entry_id: Option<i32>,
+    new_success: bool,
+) -> Result<(), Error> {
+    use schema::audit_log::dsl::*;
+
+    let last_hash = audit_log
+        .select(hash)
+        .order(id.desc())
+        .first::<String>(conn)
+        .optional()?
+        .unwrap_or_else(|| AUDIT_LOG_GENESIS.to_string());
+
+    let mut entry = models::AuditLog {
+        id: None,
+        timestamp: now(),
+        peer: audit.peer.clone(),
+        action: audit.action.clone(),
+        entry_id: new_entry_id,
+        success: new_success,
+        prev_hash: last_hash,
+        hash: String::new(),
+    };
+    entry.hash = audit_log_hash(&entry);
+
+    diesel::insert_into(audit_log)
+        .values(&entry)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// hash of an audit log entry, `id` and `hash` itself are left out,
+/// a removed entry is noticed since the next one no longer chains to it
+fn audit_log_hash(entry: &models::AuditLog) -> String {
+    let entry_id = entry.entry_id.map(|id| id.to_string()).unwrap_or_default();
+    let content = format!(
+        "{}\t{}\t{}\t{}\t{}\t{}",
+        entry.prev_hash, entry.timestamp, entry.peer, entry.action, entry_id, entry.success
+    );
+
+    Sha256::digest(content.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// whether every entry is chained to the one before and its hash matches
+fn verify_audit_log(entries: &[models::AuditLog]) -> bool {
+    let mut prev_hash = AUDIT_LOG_GENESIS;
+    for entry in entries {
+        if entry.prev_hash != prev_hash || audit_log_hash(entry) != entry.hash {
+            return false;
+        }
+        prev_hash = &entry.hash;
+    }
+    true
+}
+
+/// The clients send the fields base64 encoded,
+/// fall back to the raw field if it is not
+pub fn decode_field(data: &str) -> String {
+    let mut decoder = DecoderReader::new(data.as_bytes(), &STANDARD);
+    let mut decoded = Vec::new();
+    match decoder.read_to_end(&mut decoded) {
+        Ok(_) => String::from_utf8(decoded).unwrap_or_else(|_| data.to_string()),
+        Err(_) => data.to_string(),
+    }
+}
+
+/// host of an url, or the whole string if it has no scheme and path
+fn host_of(url: &str) -> &str {
+    let url = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let host = url.split(['/', '?', '#']).next().unwrap_or(url);
+    let host = host.rsplit_once('@').map_or(host, |(_, host)| host);
+    host.split(':').next().unwrap_or(host)
+}
+
+/// `query` has to be lowercase
+fn matches_query(item: &models::WebsiteAccount, query: &str) -> bool {
+    let site_url = decode_field(&item.site_url).to_lowercase();
+
+    let fields = [
+        Some(&item.account),
+        Some(&item.site_url),
+        item.site_name.as_ref(),
+        item.note.as_ref(),
+    ];
+    let substring = fields
+        .into_iter()
+        .flatten()
+        .any(|field| decode_field(field).to_lowercase().contains(query));
+
+    let domain = host_of(query);
+    let host = host_of(&site_url);
+    let on_domain =
+        domain.contains('.') && (host == domain || host.ends_with(&format!(".{}", domain)));
+
+    substring || on_domain
+}
+
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
     use super::*;
+    use crate::encrypt::legacy_key;
+
+    fn audit() -> Audit {
+        Audit {
+            peer: "127.0.0.1".to_string(),
+            action: "test".to_string(),
+        }
+    }
 
     #[tokio::test]
     async fn test_db() {
@@ -190,9 +1289,11 @@ mod tests {
         let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
 
         let db = Db::new(&url);
+        db.set_data_key(legacy_key().unwrap());
 
         if db
             .add_new_website_account(
+                &audit(),
                 "test_account".to_string(),
                 "test_password".to_string(),
                 "www.baidu.com".to_string(),
@@ -207,15 +1308,589 @@ mod tests {
 
         match db.get_website_id_by_account("test_account").await {
             Ok(Some(id)) => {
-                if (db.get_website_account_password(id).await).is_err() {
-                    panic!("Failed to get website account password");
+                match db.get_website_account_password(id).await {
+                    Ok(Some(password)) => assert_eq!(password, "test_password"),
+                    _ => panic!("Failed to get website account password"),
                 }
 
-                if (db.delete_website_account(id).await).is_err() {
+                if (db.delete_website_account(&audit(), id).await).is_err() {
                     panic!("Failed to delete website account");
                 }
+
+                match db.get_website_account_password(id).await {
+                    Ok(None) => {}
+                    _ => panic!("Deleted website account still has a password"),
+                }
             }
             _ => panic!("Failed to get website id by account"),
         }
     }
+
+    #[test]
+    fn test_matches_query() {
+        let item = models::WebsiteAccount {
+            id: Some(1),
+            account: "bXlfYWNjb3VudA==".to_string(), // my_account
+            password: "encrypted".to_string(),
+            site_url: "https://mail.example.com/login".to_string(),
+            site_name: Some("Example Mail".to_string()),
+            note: None,
+            updated_at: 0,
+        };
+
+        assert!(matches_query(&item, "my_acc"));
+        assert!(matches_query(&item, "example mail"));
+        assert!(matches_query(&item, "example.com"));
+        assert!(matches_query(&item, "https://example.com"));
+        assert!(!matches_query(&item, "encrypted"));
+        assert!(!matches_query(&item, "other.com"));
+        assert!(!matches_query(&item, "ample.org"));
+    }
+
+    #[test]
+    fn test_paginate() {
+        let item =
+            |id: i32, name: Option<&str>, url: &str, updated_at: i64| models::WebsiteAccount {
+                id: Some(id),
+                account: "account".to_string(),
+                password: "encrypted".to_string(),
+                site_url: url.to_string(),
+                site_name: name.map(|name| name.to_string()),
+                note: None,
+                updated_at,
+            };
+        let list = || {
+            vec![
+                item(2, Some("YmFpZHU="), "https://c.com", 30), // baidu
+                item(1, None, "https://a.com", 10),
+                item(3, Some("Z2l0aHVi"), "https://b.com", 20), // github
+            ]
+        };
+        let ids = |(_, page): (usize, Vec<models::WebsiteAccount>)| {
+            page.iter().map(|item| item.id.unwrap()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            ids(paginate(list(), SortKey::Id, None, None)),
+            vec![1, 2, 3]
+        );
+        assert_eq!(
+            ids(paginate(list(), SortKey::Name, None, None)),
+            vec![2, 3, 1]
+        );
+        assert_eq!(
+            ids(paginate(list(), SortKey::Url, None, None)),
+            vec![1, 3, 2]
+        );
+        assert_eq!(
+            ids(paginate(list(), SortKey::Modified, None, None)),
+            vec![2, 3, 1]
+        );
+
+        let (total, page) = paginate(list(), SortKey::Id, Some(1), Some(1));
+        assert_eq!(total, 3);
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].id, Some(2));
+    }
+
+    #[tokio::test]
+    async fn test_device() {
+        let db = Db::in_memory("test_device");
+
+        let fingerprint = "test_fingerprint".to_string();
+        if db
+            .add_device(&audit(), "test_device".to_string(), fingerprint.clone())
+            .await
+            .is_err()
+        {
+            panic!("Failed to add device");
+        }
+
+        let device = match db.get_device_by_fingerprint(&fingerprint).await {
+            Ok(Some(device)) => device,
+            _ => panic!("Failed to get device by fingerprint"),
+        };
+        assert!(!device.revoked);
+
+        if db
+            .revoke_device(&audit(), device.id.unwrap())
+            .await
+            .is_err()
+        {
+            panic!("Failed to revoke device");
+        }
+
+        match db.get_device_by_fingerprint(&fingerprint).await {
+            Ok(Some(device)) => assert!(device.revoked),
+            _ => panic!("Failed to get device by fingerprint"),
+        }
+        assert!(matches!(
+            db.revoke_device(&audit(), -1).await,
+            Err(DbError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_patch_website_account() {
+        let db = Db::in_memory("test_patch_website_account");
+        db.set_data_key(Some(DataKey::generate()));
+
+        let account = "patch_account".to_string();
+        let website_id = match db
+            .add_new_website_account(
+                &audit(),
+                account.clone(),
+                "patch_password".to_string(),
+                "www.baidu.com".to_string(),
+                Some("baidu".to_string()),
+                Some("nothing".to_string()),
+            )
+            .await
+        {
+            Ok(website_id) => website_id,
+            Err(_) => panic!("Failed to add new website account"),
+        };
+
+        // only the note is changed, the name is cleared
+        if db
+            .patch_website_account(
+                &audit(),
+                website_id,
+                None,
+                None,
+                None,
+                Some(None),
+                Some(Some("something".to_string())),
+            )
+            .await
+            .is_err()
+        {
+            panic!("Failed to patch website account");
+        }
+
+        let patched = match db.get_all_website_account().await {
+            Ok(list) => list.into_iter().find(|item| item.id == Some(website_id)),
+            Err(_) => panic!("Failed to get all website account"),
+        };
+        match patched {
+            Some(item) => {
+                assert_eq!(item.account, account);
+                assert_eq!(item.site_url, "www.baidu.com");
+                assert_eq!(item.site_name, None);
+                assert_eq!(item.note.as_deref(), Some("something"));
+            }
+            None => panic!("Patched website account is missing"),
+        }
+        match db.get_website_account_password(website_id).await {
+            Ok(Some(password)) => assert_eq!(password, "patch_password"),
+            _ => panic!("Failed to get website account password"),
+        }
+
+        assert!(matches!(
+            db.patch_website_account(&audit(), -1, None, None, None, None, None)
+                .await,
+            Err(DbError::NotFound)
+        ));
+        assert!(matches!(
+            db.update_website_account(
+                &audit(),
+                -1,
+                "account".to_string(),
+                "password".to_string(),
+                None,
+                "url".to_string(),
+                None
+            )
+            .await,
+            Err(DbError::NotFound)
+        ));
+
+        if db
+            .delete_website_account(&audit(), website_id)
+            .await
+            .is_err()
+        {
+            panic!("Failed to delete website account");
+        }
+        assert!(matches!(
+            db.delete_website_account(&audit(), website_id).await,
+            Err(DbError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_events() {
+        let db = Db::in_memory("test_events");
+        db.set_data_key(Some(DataKey::generate()));
+        let mut events = db.subscribe();
+
+        let website_id = match db
+            .add_new_website_account(
+                &audit(),
+                "event_account".to_string(),
+                "event_password".to_string(),
+                "www.baidu.com".to_string(),
+                None,
+                None,
+            )
+            .await
+        {
+            Ok(website_id) => website_id,
+            Err(_) => panic!("Failed to add new website account"),
+        };
+        assert_eq!(events.try_recv(), Ok(Event::Added { id: website_id }));
+
+        // the first status is no change, the second one is
+        db.update_dead_links(&[(website_id, false)]);
+        db.update_dead_links(&[(website_id, false)]);
+        db.update_dead_links(&[(website_id, true)]);
+        assert_eq!(
+            events.try_recv(),
+            Ok(Event::DeadLink {
+                id: website_id,
+                dead_link: true
+            })
+        );
+
+        if db
+            .delete_website_account(&audit(), website_id)
+            .await
+            .is_err()
+        {
+            panic!("Failed to delete website account");
+        }
+        assert_eq!(events.try_recv(), Ok(Event::Deleted { id: website_id }));
+        assert!(events.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_batch() {
+        let db = Db::in_memory("test_batch");
+        db.set_data_key(Some(DataKey::generate()));
+
+        let account = "batch_account".to_string();
+        let add = BatchItem::AddWebsiteAccount {
+            account: account.clone(),
+            password: "batch_password".to_string(),
+            site_url: "www.baidu.com".to_string(),
+            site_name: None,
+            note: None,
+        };
+
+        // the second item fails, so the first one is rolled back
+        match db
+            .batch(
+                &audit(),
+                vec![
+                    add.clone(),
+                    BatchItem::DeleteWebsiteAccount { website_id: -1 },
+                ],
+            )
+            .await
+        {
+            Err(BatchError {
+                index: Some(1),
+                error: DbError::NotFound,
+            }) => {}
+            _ => panic!("Batch should fail at the second item"),
+        }
+        assert!(matches!(
+            db.get_website_id_by_account(&account).await,
+            Ok(None)
+        ));
+
+        let ids = match db.batch(&audit(), vec![add]).await {
+            Ok(ids) => ids,
+            Err(_) => panic!("Failed to run batch"),
+        };
+        assert_eq!(ids.len(), 1);
+
+        match db
+            .batch(
+                &audit(),
+                vec![
+                    BatchItem::ChangeWebsiteAccount {
+                        id: ids[0],
+                        new_account: account.clone(),
+                        new_password: "new_password".to_string(),
+                        new_site_name: Some("baidu".to_string()),
+                        new_site_url: "www.baidu.com".to_string(),
+                        new_note: None,
+                    },
+                    BatchItem::DeleteWebsiteAccount { website_id: ids[0] },
+                ],
+            )
+            .await
+        {
+            Ok(result) => assert_eq!(result, vec![ids[0], ids[0]]),
+            Err(_) => panic!("Failed to run batch"),
+        }
+        assert!(matches!(
+            db.get_website_id_by_account(&account).await,
+            Ok(None)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_batch_audit_log() {
+        let db = Db::in_memory("test_batch_audit_log");
+        db.set_data_key(Some(DataKey::generate()));
+        let add = BatchItem::AddWebsiteAccount {
+            account: "batch_account".to_string(),
+            password: "batch_password".to_string(),
+            site_url: "www.baidu.com".to_string(),
+            site_name: None,
+            note: None,
+        };
+
+        // a rolled back batch leaves no entry
+        assert!(db
+            .batch(
+                &audit(),
+                vec![
+                    add.clone(),
+                    BatchItem::DeleteWebsiteAccount { website_id: -1 },
+                ]
+            )
+            .await
+            .is_err());
+        assert_eq!(db.get_audit_log(None, None).await.unwrap().0, 0);
+
+        let ids = db
+            .batch(
+                &audit(),
+                vec![
+                    add.clone(),
+                    add,
+                    BatchItem::DeleteWebsiteAccount { website_id: 1 },
+                ],
+            )
+            .await
+            .unwrap();
+
+        // an entry for every item in order, then the batch itself
+        let (total, valid, mut list) = db.get_audit_log(None, None).await.unwrap();
+        assert!(valid);
+        assert_eq!(total, ids.len() + 1);
+        list.reverse();
+        let logged: Vec<_> = list
+            .iter()
+            .map(|entry| (entry.action.as_str(), entry.entry_id))
+            .collect();
+        assert_eq!(
+            logged,
+            vec![
+                ("add_website_account", Some(ids[0])),
+                ("add_website_account", Some(ids[1])),
+                ("delete_website_account", Some(ids[2])),
+                ("test", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_verify_audit_log() {
+        let mut entries: Vec<models::AuditLog> = Vec::new();
+        for (i, action) in ["check_identity", "get_info", "delete_website_account"]
+            .into_iter()
+            .enumerate()
+        {
+            let mut entry = models::AuditLog {
+                id: Some(i as i32 + 1),
+                timestamp: 1_700_000_000 + i as i64,
+                peer: "127.0.0.1".to_string(),
+                action: action.to_string(),
+                entry_id: Some(1),
+                success: true,
+                prev_hash: entries
+                    .last()
+                    .map_or(AUDIT_LOG_GENESIS.to_string(), |e| e.hash.clone()),
+                hash: String::new(),
+            };
+            entry.hash = audit_log_hash(&entry);
+            entries.push(entry);
+        }
+        assert!(verify_audit_log(&entries));
+
+        // edited
+        let mut edited = entries.clone();
+        edited[1].success = false;
+        assert!(!verify_audit_log(&edited));
+
+        // edited, and the hash fixed up
+        edited[1].hash = audit_log_hash(&edited[1]);
+        assert!(!verify_audit_log(&edited));
+
+        // removed
+        let mut removed = entries.clone();
+        removed.remove(1);
+        assert!(!verify_audit_log(&removed));
+    }
+
+    #[tokio::test]
+    async fn test_audit_log() {
+        let db = Db::in_memory("test_audit_log");
+
+        if db
+            .add_audit_log("127.0.0.1".to_string(), "get_info".to_string(), None, true)
+            .await
+            .is_err()
+        {
+            panic!("Failed to add audit log");
+        }
+
+        match db.get_audit_log(Some(1), None).await {
+            Ok((total, valid, list)) => {
+                assert_eq!(total, 1);
+                assert!(valid);
+                assert_eq!(list.len(), 1);
+            }
+            Err(_) => panic!("Failed to get audit log"),
+        }
+
+        // a change writes its entry in the same transaction, a failed one none
+        db.set_data_key(Some(DataKey::generate()));
+        let website_id = db
+            .add_new_website_account(
+                &audit(),
+                "audit_account".to_string(),
+                "audit_password".to_string(),
+                "www.baidu.com".to_string(),
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        db.delete_website_account(&audit(), website_id)
+            .await
+            .unwrap();
+        assert!(db
+            .delete_website_account(&audit(), website_id)
+            .await
+            .is_err());
+
+        let (total, valid, list) = db.get_audit_log(None, None).await.unwrap();
+        assert_eq!(total, 3);
+        assert!(valid);
+        assert!(list[..2]
+            .iter()
+            .all(|entry| entry.action == "test" && entry.entry_id == Some(website_id)));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn test_audit_log_concurrent() {
+        let db = Arc::new(Db::in_temp_file("test_audit_log_concurrent"));
+
+        // every write waits for the lock instead of failing
+        let tasks: Vec<_> = (0..8)
+            .map(|task| {
+                let db = db.clone();
+                tokio::spawn(async move {
+                    for _ in 0..50 {
+                        db.add_audit_log(
+                            format!("peer_{}", task),
+                            "get_info".to_string(),
+                            None,
+                            true,
+                        )
+                        .await
+                        .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let (total, valid, _) = db.get_audit_log(Some(1), None).await.unwrap();
+        assert_eq!(total, 400);
+        assert!(valid);
+    }
+
+    #[tokio::test]
+    async fn test_auth_failure() {
+        let db = Db::in_memory("test_auth_failure");
+
+        let peer = "test_peer".to_string();
+        for failures in 1..=2 {
+            let failure = models::AuthFailure {
+                peer: peer.clone(),
+                failures,
+                locked_until: 0,
+            };
+            if db.set_auth_failure(failure).await.is_err() {
+                panic!("Failed to set auth failure");
+            }
+        }
+
+        match db.get_auth_failure(&peer).await {
+            Ok(Some(failure)) => assert_eq!(failure.failures, 2),
+            _ => panic!("Failed to get auth failure"),
+        }
+
+        if db.delete_auth_failure(&peer).await.is_err() {
+            panic!("Failed to delete auth failure");
+        }
+        assert!(matches!(db.get_auth_failure(&peer).await, Ok(None)));
+    }
+
+    #[tokio::test]
+    async fn test_first_data_key() {
+        let db = Db::in_memory("test_first_data_key");
+        db.set_master_password_hash("hash".to_string())
+            .await
+            .unwrap();
+        assert!(db.first_data_key(None).is_ok());
+
+        // a new key can't decrypt the passwords stored with the old one
+        db.set_data_key(Some(DataKey::generate()));
+        db.add_new_website_account(
+            &audit(),
+            "account".to_string(),
+            "password".to_string(),
+            "url".to_string(),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        db.lock();
+        assert!(matches!(db.first_data_key(None), Err(DbError::KeyMissing)));
+        assert!(db.first_data_key(Some(DataKey::generate())).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_totp() {
+        let db = Db::in_memory("test_totp");
+        assert!(matches!(
+            db.enroll_totp(&audit()).await,
+            Err(DbError::Locked)
+        ));
+        let data_key = DataKey::generate();
+        db.set_data_key(Some(data_key.clone()));
+
+        // not required until a code of it is confirmed
+        let secret = db.enroll_totp(&audit()).await.unwrap();
+        assert!(!db.totp_enabled().await.unwrap());
+        assert!(db
+            .confirm_totp(&audit(), "not a code")
+            .await
+            .unwrap()
+            .is_none());
+
+        let code = totp::code_at(&secret, totp::step_of(now() as u64));
+        let recovery_codes = db.confirm_totp(&audit(), &code).await.unwrap().unwrap();
+        assert!(db.totp_enabled().await.unwrap());
+
+        // each code works once only
+        assert!(!db.verify_totp(&data_key, &code).await.unwrap());
+        assert!(db.verify_totp(&data_key, &recovery_codes[0]).await.unwrap());
+        assert!(!db.verify_totp(&data_key, &recovery_codes[0]).await.unwrap());
+
+        assert!(!db.disable_totp(&audit(), "aaaaa-aaaaa").await.unwrap());
+        assert!(db.disable_totp(&audit(), &recovery_codes[1]).await.unwrap());
+        assert!(!db.totp_enabled().await.unwrap());
+        assert!(!db.verify_totp(&data_key, &recovery_codes[2]).await.unwrap());
+    }
 }
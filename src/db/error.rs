@@ -0,0 +1,45 @@
+use std::fmt;
+
+/// Why a database operation failed
+#[derive(Debug)]
+pub enum DbError {
+    /// the query itself failed
+    Query(diesel::result::Error),
+    /// there is no such row
+    NotFound,
+    /// a password could not be encrypted or decrypted
+    Crypto,
+    /// no connection could be taken from the pool
+    PoolUnavailable(String),
+    /// the data key is not in memory, the vault has to be unlocked first
+    Locked,
+    /// passwords are stored already, but there is no key to wrap on the first unlock
+    KeyMissing,
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DbError::Query(e) => write!(f, "{}", e),
+            DbError::NotFound => write!(f, "No such entry"),
+            DbError::Crypto => write!(f, "Failed to encrypt or decrypt the password"),
+            DbError::PoolUnavailable(e) => write!(f, "No database connection available: {}", e),
+            DbError::Locked => write!(f, "The vault is locked"),
+            DbError::KeyMissing => write!(
+                f,
+                "The stored passwords are encrypted with KEY, set it for the first unlock"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DbError {}
+
+impl From<diesel::result::Error> for DbError {
+    fn from(e: diesel::result::Error) -> Self {
+        match e {
+            diesel::result::Error::NotFound => DbError::NotFound,
+            e => DbError::Query(e),
+        }
+    }
+}
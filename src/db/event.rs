@@ -0,0 +1,44 @@
+use serde::{Deserialize, Serialize};
+
+/// How many events a slow subscriber may fall behind before it misses some
+pub const EVENT_CAPACITY: usize = 64;
+
+/// A change of the website accounts, sent to every subscriber
+///
+/// In JSON: `{"event": "changed", "id": 3}`
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Added {
+        id: i32,
+    },
+    Changed {
+        id: i32,
+    },
+    Deleted {
+        id: i32,
+    },
+    /// the site turned dead or came back
+    DeadLink {
+        id: i32,
+        dead_link: bool,
+    },
+    /// the subscriber was too slow and missed `missed` events,
+    /// fetch everything again
+    Lagged {
+        missed: u64,
+    },
+}
+
+impl Event {
+    /// name of the event, same as in JSON
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::Added { .. } => "added",
+            Event::Changed { .. } => "changed",
+            Event::Deleted { .. } => "deleted",
+            Event::DeadLink { .. } => "dead_link",
+            Event::Lagged { .. } => "lagged",
+        }
+    }
+}
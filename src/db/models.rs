@@ -1,5 +1,6 @@
 use crate::db::schema;
 use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
 
 #[derive(Queryable, Selectable, Insertable)]
 #[diesel(table_name = schema::website_account)]
@@ -11,14 +12,105 @@ pub struct WebsiteAccount {
     pub site_url: String,
     pub site_name: Option<String>,
     pub note: Option<String>,
+    /// unix time of the last change
+    pub updated_at: i64,
 }
 
+/// The fields of a website account to change, `None` leaves a field as it is
+///
+/// `Some(None)` clears `site_name` or `note`.
+#[derive(AsChangeset)]
+#[diesel(table_name = schema::website_account)]
+pub struct WebsiteAccountPatch {
+    pub account: Option<String>,
+    /// encrypted already
+    pub password: Option<String>,
+    pub site_url: Option<String>,
+    pub site_name: Option<Option<String>>,
+    pub note: Option<Option<String>>,
+    pub updated_at: i64,
+}
+
+/// A website account without its password
+#[derive(Debug, Serialize, Deserialize)]
 pub struct WebsiteAccountWithDeadLink {
     pub id: Option<i32>,
     pub account: String,
-    pub password: String,
     pub site_url: String,
     pub site_name: Option<String>,
     pub note: Option<String>,
+    pub updated_at: i64,
     pub dead_link: bool,
 }
+
+/// A client certificate enrolled for mutual TLS
+#[derive(Debug, Queryable, Selectable, Insertable, Serialize, Deserialize, Clone)]
+#[diesel(table_name = schema::device)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Device {
+    pub id: Option<i32>,
+    pub label: String,
+    /// SHA-256 of the DER certificate, in hex
+    pub fingerprint: String,
+    pub revoked: bool,
+}
+
+/// Failed CheckIdentity attempts of a peer, see `process::lockout`
+#[derive(Queryable, Selectable, Insertable, AsChangeset, Clone)]
+#[diesel(table_name = schema::auth_failure)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct AuthFailure {
+    pub peer: String,
+    pub failures: i32,
+    /// unix time until which the peer is locked out
+    pub locked_until: i64,
+}
+
+/// The one row of the vault settings, `id` is always 1
+#[derive(Queryable, Selectable, Insertable, AsChangeset, Clone)]
+#[diesel(table_name = schema::vault)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Vault {
+    pub id: i32,
+    /// Argon2id PHC string of the master password
+    pub password_hash: String,
+    /// salt of the key derived from the master password, see `encrypt::derive_key`
+    pub key_salt: Option<String>,
+    /// the data key encrypted with the derived key, `None` before the first unlock
+    pub wrapped_key: Option<String>,
+}
+
+/// The TOTP secret, `id` is always 1
+#[derive(Queryable, Selectable, Insertable, AsChangeset, Clone)]
+#[diesel(table_name = schema::totp)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Totp {
+    pub id: i32,
+    /// base32 secret encrypted with the data key
+    pub secret: String,
+    /// whether a code was entered since enrollment, only then it is required
+    pub confirmed: bool,
+    /// the last time step a code was accepted for, older ones are refused
+    pub last_step: Option<i64>,
+}
+
+/// One entry of the audit log, never holds a secret
+///
+/// `hash` covers the other fields and `prev_hash`,
+/// so changing or removing an entry breaks the chain.
+#[derive(Debug, Queryable, Selectable, Insertable, Serialize, Deserialize, Clone)]
+#[diesel(table_name = schema::audit_log)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct AuditLog {
+    pub id: Option<i32>,
+    /// unix time
+    pub timestamp: i64,
+    pub peer: String,
+    /// name of the action, like `delete_website_account`
+    pub action: String,
+    /// the website account or device the action was about
+    pub entry_id: Option<i32>,
+    pub success: bool,
+    pub prev_hash: String,
+    pub hash: String,
+}
@@ -1,5 +1,60 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    audit_log (id) {
+        id -> Nullable<Integer>,
+        timestamp -> BigInt,
+        peer -> Text,
+        action -> Text,
+        entry_id -> Nullable<Integer>,
+        success -> Bool,
+        prev_hash -> Text,
+        hash -> Text,
+    }
+}
+
+diesel::table! {
+    auth_failure (peer) {
+        peer -> Text,
+        failures -> Integer,
+        locked_until -> BigInt,
+    }
+}
+
+diesel::table! {
+    device (id) {
+        id -> Nullable<Integer>,
+        label -> Text,
+        fingerprint -> Text,
+        revoked -> Bool,
+    }
+}
+
+diesel::table! {
+    recovery_code (id) {
+        id -> Nullable<Integer>,
+        code_hash -> Text,
+    }
+}
+
+diesel::table! {
+    totp (id) {
+        id -> Integer,
+        secret -> Text,
+        confirmed -> Bool,
+        last_step -> Nullable<BigInt>,
+    }
+}
+
+diesel::table! {
+    vault (id) {
+        id -> Integer,
+        password_hash -> Text,
+        key_salt -> Nullable<Text>,
+        wrapped_key -> Nullable<Text>,
+    }
+}
+
 diesel::table! {
     website_account (id) {
         id -> Nullable<Integer>,
@@ -8,5 +63,16 @@ diesel::table! {
         site_url -> Text,
         site_name -> Nullable<Text>,
         note -> Nullable<Text>,
+        updated_at -> BigInt,
     }
 }
+
+diesel::allow_tables_to_appear_in_same_query!(
+    audit_log,
+    auth_failure,
+    device,
+    recovery_code,
+    totp,
+    vault,
+    website_account,
+);
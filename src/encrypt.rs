@@ -4,19 +4,95 @@ use aes_gcm::{
     aead::{self, Aead, AeadCore, KeyInit, OsRng},
     Aes256Gcm, Key,
 };
+use argon2::password_hash::rand_core::RngCore;
+use argon2::Argon2;
 
 use base64::engine::general_purpose::STANDARD;
 use base64::read::DecoderReader;
 use base64::write::EncoderWriter;
+use zeroize::Zeroize;
 
-pub async fn encrypt(password: String) -> Result<String, aead::Error> {
-    let key = get_key();
-    let key = Key::<Aes256Gcm>::from_slice(&key);
+/// The AES-256 key of the passwords, wiped from memory when dropped
+#[derive(Clone)]
+pub struct DataKey([u8; 32]);
+
+impl DataKey {
+    pub fn generate() -> Self {
+        DataKey(Aes256Gcm::generate_key(OsRng).into())
+    }
+}
+
+impl Drop for DataKey {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+pub async fn encrypt(key: &DataKey, password: String) -> Result<String, aead::Error> {
+    seal(key, password.as_bytes())
+}
+
+pub async fn decrypt(key: &DataKey, data: String) -> Result<String, aead::Error> {
+    let plaintext = open(key, &data)?;
+
+    String::from_utf8(plaintext).map_err(|_| aead::Error)
+}
+
+/// the key in `KEY` of the environment (or `.env`), the legacy place to keep it
+///
+/// `Err` if `KEY` is set but is not 32 bytes in base64.
+pub fn legacy_key() -> Result<Option<DataKey>, aead::Error> {
+    dotenv::dotenv().ok();
+    match std::env::var("KEY") {
+        Ok(key) => parse_key(key).map(Some),
+        Err(_) => Ok(None),
+    }
+}
+
+fn parse_key(key: String) -> Result<DataKey, aead::Error> {
+    let mut key = decode(key).map_err(|_| aead::Error)?;
+    let parsed = key.as_slice().try_into().map(DataKey);
+    key.zeroize();
+    parsed.map_err(|_| aead::Error)
+}
+
+/// a random salt for `derive_key`
+pub fn generate_salt() -> String {
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    encode(salt.to_vec())
+}
+
+/// derive a key from the master password with Argon2id, it only wraps the data key
+pub fn derive_key(password: &str, salt: &str) -> Result<DataKey, aead::Error> {
+    let salt = decode(salt.to_string()).map_err(|_| aead::Error)?;
+    let mut key = DataKey([0u8; 32]);
+    Argon2::default()
+        .hash_password_into(password.as_bytes(), &salt, &mut key.0)
+        .map_err(|_| aead::Error)?;
+    Ok(key)
+}
+
+/// encrypt the data key with the key derived from the master password
+pub fn wrap_key(kek: &DataKey, key: &DataKey) -> Result<String, aead::Error> {
+    seal(kek, &key.0)
+}
+
+pub fn unwrap_key(kek: &DataKey, wrapped: &str) -> Result<DataKey, aead::Error> {
+    let mut key = open(kek, wrapped)?;
+    let unwrapped = key.as_slice().try_into().map(DataKey);
+    key.zeroize();
+    unwrapped.map_err(|_| aead::Error)
+}
+
+/// encrypt as `CIPHERTEXT:NONCE`, both base64
+fn seal(key: &DataKey, plaintext: &[u8]) -> Result<String, aead::Error> {
+    let key = Key::<Aes256Gcm>::from_slice(&key.0);
 
     let cipher = Aes256Gcm::new(key);
     let nonce = Aes256Gcm::generate_nonce(&mut OsRng); // 96-bits; unique per message
 
-    let ciphertext = cipher.encrypt(&nonce, password.as_bytes().as_ref())?;
+    let ciphertext = cipher.encrypt(&nonce, plaintext)?;
 
     let result = format!(
         "{}:{}",
@@ -27,33 +103,22 @@ pub async fn encrypt(password: String) -> Result<String, aead::Error> {
     Ok(result)
 }
 
-pub async fn decrypt(data: String) -> Result<String, aead::Error> {
-    let key = get_key();
-    let key = Key::<Aes256Gcm>::from_slice(&key);
+fn open(key: &DataKey, data: &str) -> Result<Vec<u8>, aead::Error> {
+    let key = Key::<Aes256Gcm>::from_slice(&key.0);
 
     let cipher = Aes256Gcm::new(key);
-    let mut data = data.split(':');
-
-    let chiphertext = data.next().unwrap();
-    let nonce = data.next().unwrap();
+    // Broken data is reported as a failed decryption instead of a panic
+    let (chiphertext, nonce) = data.split_once(':').ok_or(aead::Error)?;
 
-    let chiphertext = decode(chiphertext.to_string());
-    let nonce = decode(nonce.to_string());
+    let chiphertext = decode(chiphertext.to_string()).map_err(|_| aead::Error)?;
+    let nonce = decode(nonce.to_string()).map_err(|_| aead::Error)?;
+    if nonce.len() != 12 {
+        return Err(aead::Error);
+    }
 
     let nonce = aes_gcm::Nonce::from_slice(&nonce);
 
-    let plaintext = cipher.decrypt(nonce, chiphertext.as_ref())?;
-
-    Ok(String::from_utf8(plaintext).unwrap())
-}
-
-fn get_key() -> [u8; 32] {
-    dotenv::dotenv().ok();
-    let key = std::env::var("KEY").expect("Here is no key");
-
-    let key = decode(key);
-
-    key.as_slice().try_into().unwrap()
+    cipher.decrypt(nonce, chiphertext.as_ref())
 }
 
 fn encode(data: Vec<u8>) -> String {
@@ -63,11 +128,11 @@ fn encode(data: Vec<u8>) -> String {
     String::from_utf8(str.clone()).unwrap()
 }
 
-fn decode(data: String) -> Vec<u8> {
+fn decode(data: String) -> std::io::Result<Vec<u8>> {
     let mut decoder = DecoderReader::new(data.as_bytes(), &STANDARD);
     let mut decoded = Vec::new();
-    decoder.read_to_end(&mut decoded).unwrap();
-    decoded
+    decoder.read_to_end(&mut decoded)?;
+    Ok(decoded)
 }
 
 #[cfg(test)]
@@ -76,10 +141,42 @@ mod tests {
 
     #[tokio::test]
     async fn test_encrypt_and_decrypt() {
+        let key = DataKey::generate();
         let password = "password".to_string();
-        let encrypted = encrypt(password.clone()).await.unwrap();
-        let decrypted = decrypt(encrypted).await.unwrap();
+        let encrypted = encrypt(&key, password.clone()).await.unwrap();
+        let decrypted = decrypt(&key, encrypted.clone()).await.unwrap();
 
         assert_eq!(password, decrypted);
+
+        assert!(decrypt(&DataKey::generate(), encrypted).await.is_err());
+        assert!(decrypt(&key, "no nonce".to_string()).await.is_err());
+        assert!(decrypt(&key, "bm9uY2U=:c2hvcnQ=".to_string())
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_parse_key() {
+        let key = DataKey::generate();
+        assert_eq!(parse_key(encode(key.0.to_vec())).unwrap().0, key.0);
+
+        assert!(parse_key("not base64!".to_string()).is_err());
+        assert!(parse_key(encode(vec![0u8; 16])).is_err());
+    }
+
+    #[test]
+    fn test_wrap_key() {
+        let key = DataKey::generate();
+        let salt = generate_salt();
+
+        let kek = derive_key("master password", &salt).unwrap();
+        let wrapped = wrap_key(&kek, &key).unwrap();
+
+        // the same password and salt give the same key
+        let kek = derive_key("master password", &salt).unwrap();
+        assert_eq!(unwrap_key(&kek, &wrapped).unwrap().0, key.0);
+
+        let kek = derive_key("wrong password", &salt).unwrap();
+        assert!(unwrap_key(&kek, &wrapped).is_err());
     }
 }
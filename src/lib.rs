@@ -1,3 +1,8 @@
+pub mod config;
 pub mod db;
 pub mod process;
 pub mod encrypt;
+pub mod tls;
+pub mod unix_socket;
+pub mod client;
+pub mod totp;
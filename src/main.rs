@@ -1,25 +1,159 @@
 use std::sync::Arc;
-use tokio::net::TcpListener;
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tokio_rustls::TlsAcceptor;
+use you_should_not_pass::config::Config;
 use you_should_not_pass::db::Db;
-use you_should_not_pass::process::process;
+use you_should_not_pass::process::auth;
+use you_should_not_pass::process::connection::Peer;
+use you_should_not_pass::process::{process, Shared};
+use you_should_not_pass::{encrypt, tls, unix_socket};
 
 #[tokio::main]
 async fn main() {
-    let listner = TcpListener::bind("127.0.0.1:6123")
+    let config = Config::from_env();
+
+    let listner = TcpListener::bind(&config.listen_addr)
         .await
         .expect("Failed to bind to address");
 
-    dotenv::dotenv().ok();
-    let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let tls = config.tls.as_ref().map(|tls| {
+        tls::acceptor(&tls.cert_path, &tls.key_path, tls.client_auth)
+            .expect("Failed to load TLS certificate")
+    });
+
+    let db = Arc::new(Db::new(&config.database_url));
+    let authenticator = auth::from_config(&config.auth, &db)
+        .await
+        .expect("Failed to set up authentication");
+    // The vault starts locked, CheckIdentity unlocks it.
+    // The master password unlocks the data key, the other backends still need `KEY`
+    if !authenticator.derives_key() && encrypt::legacy_key().ok().flatten().is_none() {
+        panic!("KEY must be set unless AUTH_BACKEND is master_password");
+    }
+    let shared = Shared::new(db, authenticator, config.auto_lock_timeout);
+    let auto_lock = tokio::spawn(shared.auto_lock.clone().run());
+
+    let unix_listener = config
+        .unix_socket_path
+        .as_ref()
+        .map(|path| unix_socket::bind(path).expect("Failed to bind to unix socket"));
+
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let mut tasks = JoinSet::new();
 
-    let db = Arc::new(Db::new(&url));
+    let stop = shutdown_signal();
+    tokio::pin!(stop);
 
     loop {
-        let (socket, _) = listner.accept().await.expect("Failed to accept connection");
-        let db = db.clone();
+        tokio::select! {
+            _ = &mut stop => break,
+            accepted = listner.accept() => match accepted {
+                Ok((socket, addr)) => {
+                    let peer = Peer {
+                        addr: addr.ip().to_string(),
+                        cert_fingerprint: None,
+                    };
+                    tasks.spawn(serve_tcp(
+                        socket,
+                        peer,
+                        tls.clone(),
+                        shared.clone(),
+                        shutdown_rx.clone(),
+                    ));
+                }
+                Err(e) => eprintln!("Failed to accept connection: {}", e),
+            },
+            accepted = accept_unix(unix_listener.as_ref()) => match accepted {
+                Ok(socket) => {
+                    if !unix_socket::is_owner(&socket) {
+                        eprintln!("Refused connection from another user");
+                        continue;
+                    }
+
+                    let peer = Peer {
+                        addr: match socket.peer_cred() {
+                            Ok(cred) => format!("unix:{}", cred.uid()),
+                            Err(_) => "unix".to_string(),
+                        },
+                        cert_fingerprint: None,
+                    };
+                    tasks.spawn(process(
+                        socket,
+                        peer,
+                        shared.clone(),
+                        shutdown_rx.clone(),
+                    ));
+                }
+                Err(e) => eprintln!("Failed to accept connection: {}", e),
+            },
+            // forget the finished connections
+            Some(_) = tasks.join_next(), if !tasks.is_empty() => {}
+        }
+    }
+
+    // Stop accepting, and let the running requests finish
+    eprintln!("Shutting down, waiting for {} connection(s)", tasks.len());
+    drop(listner);
+    drop(unix_listener);
+    if let Some(path) = &config.unix_socket_path {
+        let _ = std::fs::remove_file(path);
+    }
+
+    let _ = shutdown_tx.send(true);
+    let drained = tokio::time::timeout(config.shutdown_timeout, async {
+        while tasks.join_next().await.is_some() {}
+    })
+    .await;
+
+    if drained.is_err() {
+        eprintln!("Aborting {} connection(s) after the deadline", tasks.len());
+        tasks.shutdown().await;
+    }
+
+    // the auto-lock task holds the db as well
+    auto_lock.abort();
+    let _ = auto_lock.await;
+
+    // the last references, closes the pool
+    drop(shared);
+}
+
+async fn serve_tcp(
+    socket: TcpStream,
+    mut peer: Peer,
+    tls: Option<TlsAcceptor>,
+    shared: Shared,
+    shutdown: watch::Receiver<bool>,
+) {
+    match tls {
+        Some(acceptor) => match acceptor.accept(socket).await {
+            Ok(socket) => {
+                peer.cert_fingerprint = tls::peer_fingerprint(&socket);
+                process(socket, peer, shared, shutdown).await
+            }
+            Err(e) => eprintln!("TLS handshake failed: {}", e),
+        },
+        None => process(socket, peer, shared, shutdown).await,
+    }
+}
+
+/// accept on the unix socket, never returns if there is none
+async fn accept_unix(listener: Option<&UnixListener>) -> std::io::Result<UnixStream> {
+    match listener {
+        Some(listener) => listener.accept().await.map(|(socket, _)| socket),
+        None => std::future::pending().await,
+    }
+}
+
+/// wait for SIGTERM or SIGINT
+async fn shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
 
-        tokio::spawn(async move {
-            process(socket, db).await;
-        });
+    tokio::select! {
+        _ = terminate.recv() => {}
+        _ = tokio::signal::ctrl_c() => {}
     }
 }
@@ -1,54 +1,435 @@
 mod action;
-mod auth;
+pub mod auth;
+pub mod auto_lock;
 mod check_dead_link;
+pub mod connection;
+pub(crate) mod frame;
+mod json;
+pub mod lockout;
 mod process_result;
+pub mod session;
 
-use crate::db::Db;
+use crate::db::event::Event;
+use crate::db::models::WebsiteAccountWithDeadLink;
+use crate::db::{Audit, Db};
+use crate::encrypt;
+use crate::totp;
 use action::*;
+use auth::{AuthError, Authenticator};
+use auto_lock::AutoLock;
 use check_dead_link::{check_dead_link, check_dead_link_info};
+use connection::{Connection, Peer, PROTOCOL_V1, PROTOCOL_V2};
+use frame::write_frame;
+use json::json_response;
+use lockout::{Lockout, UNCOUNTED_LOCKOUT};
 use process_result::{ProError, ProOk};
+use session::Sessions;
+use std::future::Future;
 use std::sync::Arc;
-use tokio::net::TcpStream;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::watch;
+
+/// State shared by every connection
+#[derive(Clone)]
+pub struct Shared {
+    pub db: Arc<Db>,
+    pub sessions: Arc<Sessions>,
+    pub lockout: Arc<Lockout>,
+    /// checks the password of CheckIdentity
+    pub authenticator: Arc<dyn Authenticator>,
+    pub auto_lock: Arc<AutoLock>,
+}
+
+impl Shared {
+    /// `auto_lock` is how long the vault stays unlocked without a request
+    pub fn new(
+        db: Arc<Db>,
+        authenticator: Arc<dyn Authenticator>,
+        auto_lock: Option<Duration>,
+    ) -> Self {
+        Shared {
+            lockout: Arc::new(Lockout::new(db.clone())),
+            sessions: Arc::new(Sessions::default()),
+            auto_lock: Arc::new(AutoLock::new(db.clone(), auto_lock)),
+            db,
+            authenticator,
+        }
+    }
+}
 
 /// Process the socket
-pub async fn process(socket: TcpStream, db: Arc<Db>) {
-    // Process the socket
-    let action = match read_request(&socket).await {
-        Ok(action) => action,
+///
+/// Requests are handled one after another until the peer
+/// closes the connection or sends `Logout`.
+///
+/// Once `shutdown` turns true no new request is read,
+/// a request which is already being handled is still answered.
+///
+/// After `Subscribe` the changes are pushed while waiting for the next request,
+/// until the vault is locked or the session ends, then it has to be renewed.
+pub async fn process<S>(socket: S, peer: Peer, shared: Shared, mut shutdown: watch::Receiver<bool>)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut conn = Connection::new(peer);
+    let (mut reader, mut writer) = tokio::io::split(socket);
+
+    loop {
+        let read = read_request(&mut reader, conn.version);
+        tokio::pin!(read);
+
+        // The same read goes on across the events,
+        // a request which is half read when one comes is not lost
+        let request = loop {
+            tokio::select! {
+                request = &mut read => break Some(request),
+                event = next_event(&mut conn.events) => {
+                    // a lock, or the end of the session, ends the subscription as well
+                    if !may_receive_events(&conn, &shared) {
+                        conn.events = None;
+                        continue;
+                    }
+                    let pushed = answer_request(&mut writer, conn.version, Ok(ProOk::Event(event)));
+                    if pushed.await.is_err() {
+                        break None;
+                    }
+                }
+                // the guard of `wait_for` must not be held across the awaits above
+                _ = async { let _ = shutdown.wait_for(|shutdown| *shutdown).await; } => break None,
+            }
+        };
+
+        let request = match request {
+            Some(request) => request,
+            None => break,
+        };
+        let request = match request {
+            Ok(Some(Ok(request))) => request,
+            // A request which can not be parsed is answered, the connection goes on
+            Ok(Some(Err(e))) => {
+                if (answer_request(&mut writer, conn.version, Err(e)).await).is_err() {
+                    eprintln!("Failed to answer request");
+                    break;
+                }
+                continue;
+            }
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("Failed to read request: {}", e);
+                break;
+            }
+        };
+
+        let logout = request.action == Action::Logout;
+        let hello = match request.action {
+            Action::Hello { version } => Some(version),
+            _ => None,
+        };
+
+        let result = handle_action(request, &mut conn, &shared).await;
+        let accepted = result.is_ok();
+
+        // Hello is answered in the old version, the new one is used afterwards
+        if (answer_request(&mut writer, conn.version, result).await).is_err() {
+            eprintln!("Failed to answer request");
+            break;
+        }
+
+        if logout {
+            break;
+        }
+        if let Some(version) = hello.filter(|_| accepted) {
+            conn.version = version;
+        }
+    }
+}
+
+/// run a check of the password or TOTP code,
+/// refused while the peer is locked out and counted by the lockout
+///
+/// The attempt is counted as a failure before it is checked and only a success
+/// takes it back, so a guess is counted whatever happens to the check.
+/// A peer whose attempt can't be counted is treated as locked out.
+async fn check_password<T, F>(lockout: &Lockout, peer: &str, check: F) -> Result<T, ProError>
+where
+    F: Future<Output = Result<T, ProError>>,
+{
+    // Refuse locked out peers before the password is even looked at
+    let _attempt = lockout.begin().await;
+    match lockout.locked_for(peer).await {
+        Ok(Some(retry_after)) => return Err(ProError::LockedOut(retry_after)),
+        Ok(None) => {}
         Err(e) => {
-            eprintln!("Failed to read request: {}", e);
-            return;
+            eprintln!("Failed to read the lockout: {}", e);
+            return Err(ProError::LockedOut(UNCOUNTED_LOCKOUT));
+        }
+    }
+    if let Err(e) = lockout.record_failure(peer).await {
+        eprintln!("Failed to count the attempt: {}", e);
+        return Err(ProError::LockedOut(UNCOUNTED_LOCKOUT));
+    }
+
+    let checked = check.await?;
+    if let Err(e) = lockout.record_success(peer).await {
+        // the attempt stays counted as a failure, which is the safe side
+        eprintln!("Failed to reset the lockout: {}", e);
+    }
+    Ok(checked)
+}
+
+/// run a check of the `Authenticator` on a blocking thread
+async fn blocking<T, F>(check: F) -> Result<T, ProError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, AuthError> + Send + 'static,
+{
+    let checked = tokio::task::spawn_blocking(check)
+        .await
+        .unwrap_or_else(|e| Err(AuthError::Unavailable(e.to_string())));
+    Ok(checked?)
+}
+
+/// the next change for a subscribed connection,
+/// never returns if the connection is not subscribed
+async fn next_event(events: &mut Option<broadcast::Receiver<Event>>) -> Event {
+    let Some(receiver) = events else {
+        return std::future::pending().await;
+    };
+
+    match receiver.recv().await {
+        Ok(event) => event,
+        Err(RecvError::Lagged(missed)) => Event::Lagged { missed },
+        Err(RecvError::Closed) => {
+            *events = None;
+            std::future::pending().await
         }
+    }
+}
+
+/// whether the changes may still be pushed to a subscribed connection,
+/// only while the vault is unlocked and the session or device is still valid
+fn may_receive_events(conn: &Connection, shared: &Shared) -> bool {
+    let authenticated = match &conn.token {
+        Some(token) => shared.sessions.validate(token).is_some(),
+        None => false,
+    };
+    !shared.db.is_locked() && (authenticated || conn.device.is_some())
+}
+
+/// handle the request and write it to the audit log
+///
+/// No secret goes into the log, only what was done to which entry.
+async fn handle_action(
+    request: Request,
+    conn: &mut Connection,
+    shared: &Shared,
+) -> Result<ProOk, ProError> {
+    let audit = Audit {
+        peer: conn.peer.addr.clone(),
+        action: request.action.name().to_string(),
     };
+    let entry_id = request.action.entry_id();
+    let logs_with_change = request.action.logs_with_change();
+    let changes_memory = matches!(request.action, Action::Lock | Action::Logout);
 
-    let result = handle_action(action, db).await;
-    if (answer_request(&socket, result).await).is_err() {
-        eprintln!("Failed to answer request");
+    let result = perform_action(request, conn, shared, &audit).await;
+    if result.is_ok() && logs_with_change {
+        return result;
     }
+
+    if let Err(e) = shared
+        .db
+        .add_audit_log(audit.peer, audit.action, entry_id, result.is_ok())
+        .await
+    {
+        eprintln!("Failed to write audit log: {}", e);
+        // A read is answered with the error, so nothing is given out which
+        // is missing from the log. A lock or logout happened already,
+        // and a failed action did nothing
+        if result.is_ok() && !changes_memory {
+            return Err(e.into());
+        }
+    }
+
+    result
 }
 
-async fn handle_action(action: Action, db: Arc<Db>) -> Result<ProOk, ProError> {
+/// the changes of the vault write their entry of the audit log with `audit`
+async fn perform_action(
+    request: Request,
+    conn: &mut Connection,
+    shared: &Shared,
+    audit: &Audit,
+) -> Result<ProOk, ProError> {
+    let Request { token, action } = request;
+    let Shared {
+        db,
+        sessions,
+        lockout,
+        authenticator,
+        auto_lock,
+    } = shared;
+
+    // The device is looked up for every request, so a revoked one is noticed
+    conn.device = match &conn.peer.cert_fingerprint {
+        Some(fingerprint) => match db.get_device_by_fingerprint(fingerprint).await {
+            Ok(device) => device.filter(|device| !device.revoked),
+            Err(e) => return Err(e.into()),
+        },
+        None => None,
+    };
+
+    // Everything except CheckIdentity and Hello needs a valid session
+    // or an enrolled device,
+    // fall back to the session of the connection if no token is given
+    if !action.is_public() {
+        let user = match token.or_else(|| conn.token.clone()) {
+            Some(token) => sessions.validate(&token).map(|user| (token, user)),
+            None => None,
+        };
+
+        match user {
+            Some((token, user)) => {
+                conn.token = Some(token);
+                conn.user = Some(user);
+            }
+            None if conn.device.is_some() => conn.user = Some(whoami::username()),
+            None => {
+                conn.events = None;
+                return Err(ProError::Unauthenticated);
+            }
+        }
+        auto_lock.touch();
+    }
+
     match action {
-        Action::CheckIdentity { password } => {
-            // Check the password
-            if let Err(e) = auth::authourize(password).await {
-                return Err(ProError::IdentityError(e));
+        Action::CheckIdentity { password, totp } => {
+            // Without a code the password is not even looked at, so asking for
+            // the code does not use up an attempt or tell a right password apart
+            let totp_enabled = db.totp_enabled().await?;
+            if totp_enabled && totp.is_none() {
+                return Err(ProError::TotpRequired);
+            }
+
+            // `Config::from_env` checked `KEY` already, a bad one is no failed attempt
+            let legacy_key = if authenticator.derives_key() {
+                None
+            } else {
+                encrypt::legacy_key().map_err(|_| ProError::CryptoError)?
+            };
+
+            let checker = authenticator.clone();
+            let checked = password.clone();
+            let (user, data_key) = check_password(lockout, &conn.peer.addr, async {
+                let user = blocking(move || checker.authenticate(&checked)).await?;
+
+                // The data key is derived from the master password
+                // or read from the legacy `KEY`
+                let data_key = if authenticator.derives_key() {
+                    Some(db.unwrap_data_key(password).await?)
+                } else {
+                    legacy_key
+                };
+
+                // The TOTP secret is encrypted, so the code is checked with the key
+                // before the vault is unlocked
+                if let Some(code) = totp.filter(|_| totp_enabled) {
+                    let key = data_key.as_ref().ok_or(ProError::Locked)?;
+                    if !db.verify_totp(key, &code).await? {
+                        return Err(ProError::IdentityError);
+                    }
+                }
+                Ok((user, data_key))
+            })
+            .await?;
+
+            // An unlock which can't be logged does not happen
+            db.add_audit_log(audit.peer.clone(), audit.action.clone(), None, true)
+                .await?;
+
+            // Unlock the vault
+            db.set_data_key(data_key);
+            auto_lock.touch();
+
+            let token = sessions.create(user.clone());
+            conn.token = Some(token.clone());
+            conn.user = Some(user);
+
+            Ok(ProOk::Session {
+                token,
+                expires_in: sessions.ttl().as_secs(),
+            })
+        }
+        Action::Hello { version } => {
+            if version != PROTOCOL_V1 && version != PROTOCOL_V2 {
+                return Err(ProError::UnsupportedVersion(version));
+            }
+            Ok(ProOk::Ack)
+        }
+        Action::Lock => {
+            db.lock();
+            conn.events = None;
+            Ok(ProOk::Ack)
+        }
+        Action::Logout => {
+            if let Some(token) = conn.logout() {
+                sessions.revoke(&token);
             }
             Ok(ProOk::Ack)
         }
 
-        Action::GetInfo => {
-            // GetInfo
-            let list = match db.get_all_website_account().await {
-                Ok(list) => list,
-                Err(e) => return Err(ProError::DbError(e)),
+        Action::GetInfo {
+            limit,
+            offset,
+            sort,
+        } => {
+            // GetInfo, only the dead links of this page are checked
+            let (total, list) = match db
+                .get_website_account_page(sort.unwrap_or_default(), limit, offset)
+                .await
+            {
+                Ok(page) => page,
+                Err(e) => return Err(e.into()),
             };
 
             let result = check_dead_link_info(list).await;
+            db.update_dead_links(&dead_links_of(&result));
+
+            Ok(ProOk::Info {
+                total,
+                list: result,
+            })
+        }
 
-            Ok(ProOk::Info(result))
+        Action::GetWebsiteAccountPassword { website_id } => {
+            // Reveal a single password
+            match db.get_website_account_password(website_id).await {
+                Ok(Some(password)) => Ok(ProOk::Password(password)),
+                Ok(None) => Err(ProError::NotFound),
+                Err(e) => Err(e.into()),
+            }
         }
+        Action::SearchWebsiteAccount {
+            query,
+            limit,
+            offset,
+        } => {
+            // Search, and check the dead link of the results only
+            let (total, list) = match db.search_website_account(&query, limit, offset).await {
+                Ok(page) => page,
+                Err(e) => return Err(e.into()),
+            };
+
+            let result = check_dead_link_info(list).await;
+            db.update_dead_links(&dead_links_of(&result));
 
+            Ok(ProOk::Info {
+                total,
+                list: result,
+            })
+        }
         Action::AddWebsiteAccount {
             account,
             password,
@@ -58,10 +439,10 @@ async fn handle_action(action: Action, db: Arc<Db>) -> Result<ProOk, ProError> {
         } => {
             // Add the website account
             if let Err(e) = db
-                .add_new_website_account(account, password, site_url, site_name, note)
+                .add_new_website_account(audit, account, password, site_url, site_name, note)
                 .await
             {
-                return Err(ProError::DbError(e));
+                return Err(e.into());
             }
             Ok(ProOk::Ack)
         }
@@ -76,6 +457,7 @@ async fn handle_action(action: Action, db: Arc<Db>) -> Result<ProOk, ProError> {
             // Change the website account
             if let Err(e) = db
                 .update_website_account(
+                    audit,
                     id,
                     new_account,
                     new_password,
@@ -85,44 +467,220 @@ async fn handle_action(action: Action, db: Arc<Db>) -> Result<ProOk, ProError> {
                 )
                 .await
             {
-                return Err(ProError::DbError(e));
+                return Err(e.into());
+            }
+            Ok(ProOk::Ack)
+        }
+        Action::PatchWebsiteAccount {
+            id,
+            account,
+            password,
+            site_url,
+            site_name,
+            note,
+        } => {
+            // Change only the given fields
+            if let Err(e) = db
+                .patch_website_account(audit, id, account, password, site_url, site_name, note)
+                .await
+            {
+                return Err(e.into());
             }
             Ok(ProOk::Ack)
         }
         Action::DeleteWebsiteAccount { website_id } => {
             // Delete the website account
-            if let Err(e) = db.delete_website_account(website_id).await {
-                return Err(ProError::DbError(e));
+            if let Err(e) = db.delete_website_account(audit, website_id).await {
+                return Err(e.into());
+            }
+            Ok(ProOk::Ack)
+        }
+        Action::EnrollDevice { label } => {
+            // Enroll the certificate this connection was made with
+            let fingerprint = match &conn.peer.cert_fingerprint {
+                Some(fingerprint) => fingerprint.clone(),
+                None => return Err(ProError::NoClientCertificate),
+            };
+            if let Err(e) = db.add_device(audit, label, fingerprint).await {
+                return Err(e.into());
             }
             Ok(ProOk::Ack)
         }
+        Action::ListDevices => match db.get_all_device().await {
+            Ok(list) => Ok(ProOk::Devices(list)),
+            Err(e) => Err(e.into()),
+        },
+        Action::RevokeDevice { device_id } => {
+            if let Err(e) = db.revoke_device(audit, device_id).await {
+                return Err(e.into());
+            }
+            Ok(ProOk::Ack)
+        }
+        Action::Batch { actions } => {
+            // every item goes into the audit log, the batch itself as well
+            match db.batch(audit, actions).await {
+                Ok(ids) => Ok(ProOk::Batch(ids)),
+                Err(e) => Err(ProError::BatchError(e)),
+            }
+        }
+        Action::Subscribe => {
+            if db.is_locked() {
+                return Err(ProError::Locked);
+            }
+            conn.events = Some(db.subscribe());
+            Ok(ProOk::Ack)
+        }
+        Action::ChangeMasterPassword {
+            old_password,
+            new_password,
+        } => {
+            if new_password.is_empty() {
+                return Err(ProError::ParseError(
+                    "The new password is empty".to_string(),
+                ));
+            }
+
+            // Guessing the old password is limited the same way as CheckIdentity
+            let checker = authenticator.clone();
+            let (old, new) = (old_password.clone(), new_password.clone());
+            let hash = check_password(
+                lockout,
+                &conn.peer.addr,
+                blocking(move || checker.hash_new_password(&old, &new)),
+            )
+            .await?;
+
+            // The data key is wrapped again for the new password
+            db.unlock(old_password).await?;
+            db.change_master_password(audit, hash.clone(), new_password)
+                .await?;
+            authenticator.set_password_hash(hash);
+            Ok(ProOk::Ack)
+        }
+        Action::EnrollTotp => {
+            // A new secret only replaces one which was never confirmed
+            if db.totp_enabled().await? {
+                return Err(ProError::Unsupported(
+                    "TOTP is enabled already, disable it first".to_string(),
+                ));
+            }
+            let secret = db.enroll_totp(audit).await?;
+            let account = conn.user.clone().unwrap_or_else(whoami::username);
+            Ok(ProOk::TotpUri(totp::otpauth_uri(&secret, &account)))
+        }
+        Action::ConfirmTotp { code } => {
+            // Guessing codes is limited the same way as CheckIdentity
+            let codes = check_password(lockout, &conn.peer.addr, async {
+                db.confirm_totp(audit, &code)
+                    .await?
+                    .ok_or(ProError::IdentityError)
+            })
+            .await?;
+            Ok(ProOk::RecoveryCodes(codes))
+        }
+        Action::DisableTotp { code } => {
+            check_password(lockout, &conn.peer.addr, async {
+                match db.disable_totp(audit, &code).await? {
+                    true => Ok(()),
+                    false => Err(ProError::IdentityError),
+                }
+            })
+            .await?;
+            Ok(ProOk::Ack)
+        }
+        Action::GetAuditLog { limit, offset } => match db.get_audit_log(limit, offset).await {
+            Ok((total, valid, list)) => Ok(ProOk::AuditLog { total, valid, list }),
+            Err(e) => Err(e.into()),
+        },
         Action::CheckDeadLink => {
             // Check the dead link
             match db.get_all_id_and_url().await {
                 Ok(id_and_url) => {
                     // todo
                     let list = check_dead_link(id_and_url).await;
+                    db.update_dead_links(&list);
                     Ok(ProOk::DeadLink(list))
                 }
-                Err(e) => Err(ProError::DbError(e)),
+                Err(e) => Err(e.into()),
             }
         }
     }
 }
 
+fn dead_links_of(list: &[WebsiteAccountWithDeadLink]) -> Vec<(i32, bool)> {
+    list.iter()
+        .filter_map(|item| item.id.map(|id| (id, item.dead_link)))
+        .collect()
+}
+
 /// Ack: 0
-/// Info: 1
+/// Info: 1 (`"1\tTOTAL"`, then `"\nID\tACCOUNT\tSITE_URL\tSITE_NAME\tNOTE\tIS_DEAD"` for each account, no password)
 /// DeadLink: 2
 /// IdentityError: 3
 /// DbError: 4
-async fn answer_request(
-    socket: &TcpStream,
+/// Session: 5 (`"5\tTOKEN\tEXPIRES_IN_SECONDS"`)
+/// Unauthenticated: 6
+/// UnsupportedVersion: 7
+/// Devices: 8 (`"8\nID\tLABEL\tFINGERPRINT\tREVOKED"`, REVOKED is `1` or `0`)
+/// NoClientCertificate: 9
+/// Password: 10 (`"10\tPASSWORD"`)
+/// LockedOut: 11 (`"11\tRETRY_AFTER_SECONDS"`)
+/// AuditLog: 12 (`"12\tTOTAL\tVALID"`, then `"\nID\tTIMESTAMP\tPEER\tACTION\tENTRY_ID\tSUCCESS"` for each entry,
+/// VALID and SUCCESS are `1` or `0`, ENTRY_ID may be empty)
+/// Batch: 13 (`"13\tID\tID..."`, the id of the website account of every item)
+/// BatchError: 14
+/// Event: 15 (`"15\tEVENT\tID"`, EVENT is `added`, `changed` or `deleted`,
+/// `"15\tdead_link\tID\tIS_DEAD"` or `"15\tlagged\tMISSED"`, pushed after Subscribe)
+/// ParseError: 16 (`"16\tMESSAGE"`, the connection stays open)
+/// UnknownAction: 17 (`"17\tMESSAGE"`)
+/// NotFound: 18 (`"18\tMESSAGE"`)
+/// CryptoError: 19 (`"19\tMESSAGE"`)
+/// PoolUnavailable: 20 (`"20\tMESSAGE"`)
+/// UpstreamError: 21 (`"21\tMESSAGE"`)
+/// Unsupported: 22 (`"22\tMESSAGE"`)
+/// Locked: 23 (`"23\tMESSAGE"`)
+/// TotpRequired: 24 (`"24\tMESSAGE"`, TOTP is enabled, send the password again with a code)
+/// TotpUri: 25 (`"25\tOTPAUTH_URI"`)
+/// RecoveryCodes: 26 (`"26\tCODE\tCODE..."`, each can be used once instead of a TOTP code)
+///
+/// The response is sent as one frame, see `frame::write_frame`.
+/// On a version 2 connection the response is JSON, see `json::json_response`.
+async fn answer_request<W>(
+    socket: &mut W,
+    version: u8,
     result: Result<ProOk, ProError>,
-) -> Result<(), std::io::Error> {
-    let response = match result {
+) -> Result<(), std::io::Error>
+where
+    W: AsyncWrite + Unpin,
+{
+    if let Err(e) = &result {
+        eprintln!("{}: {}", e.kind(), e);
+    }
+
+    let response = if version == PROTOCOL_V2 {
+        json_response(result)
+    } else {
+        tab_response(result)
+    };
+
+    let response = response.as_bytes();
+    // Send the response
+    match write_frame(socket, response).await {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            eprintln!("Failed to write response: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// build a response of the tab separated protocol
+fn tab_response(result: Result<ProOk, ProError>) -> String {
+    match result {
         Ok(ProOk::Ack) => "0".to_string(),
-        Ok(ProOk::Info(list)) => {
-            let mut response: String = "1".to_string();
+        Ok(ProOk::Session { token, expires_in }) => format!("5\t{}\t{}", token, expires_in),
+        Ok(ProOk::Info { total, list }) => {
+            let mut response: String = format!("1\t{}", total);
             for item in list {
                 let site_name: &str = if let Some(x) = &item.site_name {
                     x.as_str()
@@ -136,12 +694,12 @@ async fn answer_request(
                     ""
                 };
 
-                let id = if let Some(x) = item.id { x } else { -1 };
+                let id = item.id.unwrap_or(-1);
                 let is_dead = if item.dead_link { "0" } else { "1" };
 
                 let res = format!(
-                    "\n{}\t{}\t{}\t{}\t{}\t{}\t{}",
-                    id, item.account, item.password, item.site_url, site_name, note, is_dead
+                    "\n{}\t{}\t{}\t{}\t{}\t{}",
+                    id, item.account, item.site_url, site_name, note, is_dead
                 );
 
                 // eprintln!("res: {}", res);
@@ -158,24 +716,105 @@ async fn answer_request(
             }
             response
         }
-        Err(ProError::IdentityError(e)) => {
-            eprintln!("IdentityError: {}", e);
-            "3".to_string()
+        Ok(ProOk::Devices(list)) => {
+            let mut response = "8".to_string();
+            for item in list {
+                let revoked = if item.revoked { "1" } else { "0" };
+                response.push_str(&format!(
+                    "\n{}\t{}\t{}\t{}",
+                    item.id.unwrap_or(-1),
+                    item.label,
+                    item.fingerprint,
+                    revoked
+                ));
+            }
+            response
         }
-        Err(ProError::DbError(e)) => {
-            eprintln!("DbError: {}", e);
-            "4".to_string()
+        Ok(ProOk::Password(password)) => format!("10\t{}", password),
+        Ok(ProOk::TotpUri(uri)) => format!("25\t{}", uri),
+        Ok(ProOk::RecoveryCodes(codes)) => {
+            let mut response = "26".to_string();
+            for code in codes {
+                response.push_str(&format!("\t{}", code));
+            }
+            response
         }
-    };
+        Ok(ProOk::Batch(ids)) => {
+            let mut response = "13".to_string();
+            for id in ids {
+                response.push_str(&format!("\t{}", id));
+            }
+            response
+        }
+        Ok(ProOk::Event(event)) => match event {
+            Event::Added { id } | Event::Changed { id } | Event::Deleted { id } => {
+                format!("15\t{}\t{}", event.name(), id)
+            }
+            Event::DeadLink { id, dead_link } => {
+                let is_dead = if dead_link { "0" } else { "1" };
+                format!("15\t{}\t{}\t{}", event.name(), id, is_dead)
+            }
+            Event::Lagged { missed } => format!("15\t{}\t{}", event.name(), missed),
+        },
+        Ok(ProOk::AuditLog { total, valid, list }) => {
+            let mut response = format!("12\t{}\t{}", total, if valid { "1" } else { "0" });
+            for item in list {
+                let entry_id = item.entry_id.map(|id| id.to_string()).unwrap_or_default();
+                let success = if item.success { "1" } else { "0" };
+                response.push_str(&format!(
+                    "\n{}\t{}\t{}\t{}\t{}\t{}",
+                    item.id.unwrap_or(-1),
+                    item.timestamp,
+                    item.peer,
+                    item.action,
+                    entry_id,
+                    success
+                ));
+            }
+            response
+        }
+        Err(ProError::LockedOut(retry_after)) => format!("11\t{}", retry_after.as_secs()),
+        // the older codes stay bare for the clients which compare them as they are
+        Err(
+            e @ (ProError::ParseError(_)
+            | ProError::UnknownAction(_)
+            | ProError::NotFound
+            | ProError::CryptoError
+            | ProError::PoolUnavailable(_)
+            | ProError::UpstreamError(_)
+            | ProError::Unsupported(_)
+            | ProError::Locked
+            | ProError::TotpRequired),
+        ) => format!("{}\t{}", e.code(), e),
+        Err(e) => e.code().to_string(),
+    }
+}
 
-    let response = response.as_bytes();
-    // Send the response
-    socket.writable().await?;
-    match socket.try_write(response) {
-        Ok(_) => Ok(()),
-        Err(e) => {
-            eprintln!("Failed to write response: {}", e);
-            Err(e)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_check_password() {
+        let db = Arc::new(Db::in_memory("test_check_password"));
+        let lockout = Lockout::new(db.clone());
+        let peer = "10.0.0.1";
+
+        // a check which does not finish is counted all the same
+        for _ in 0..3 {
+            let checked = check_password(&lockout, peer, async {
+                Err::<(), _>(ProError::UpstreamError("PAM is down".to_string()))
+            })
+            .await;
+            assert!(matches!(checked, Err(ProError::UpstreamError(_))));
         }
+        assert_eq!(
+            db.get_auth_failure(peer).await.unwrap().unwrap().failures,
+            3
+        );
+
+        let checked = check_password(&lockout, peer, async { Ok(()) }).await;
+        assert!(checked.is_ok());
+        assert!(db.get_auth_failure(peer).await.unwrap().is_none());
     }
 }
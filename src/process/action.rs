@@ -1,17 +1,39 @@
-use std::error::Error;
-use tokio::net::TcpStream;
+use serde::{Deserialize, Deserializer};
+use std::fmt::Display;
+use std::io;
+use tokio::io::AsyncRead;
 
-#[derive(Debug, PartialEq)]
+use super::connection::PROTOCOL_V2;
+use super::frame::read_frame;
+use super::json::parse_request;
+use super::process_result::ProError;
+use crate::db::{BatchItem, SortKey};
+
+/// In the JSON protocol the action is named by the `action` field,
+/// for example `{"action": "delete_website_account", "website_id": 1}`
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
 pub enum Action {
     CheckIdentity {
         password: String,
+        /// a TOTP or recovery code, needed once TOTP is enabled
+        totp: Option<String>,
     },
     // user_account
-    GetInfo,
+    GetInfo {
+        limit: Option<usize>,
+        offset: Option<usize>,
+        sort: Option<SortKey>,
+    },
     // website_account
-    // GetWebsiteAccountPassword {
-    //     website_id: i32,
-    // },
+    GetWebsiteAccountPassword {
+        website_id: i32,
+    },
+    SearchWebsiteAccount {
+        query: String,
+        limit: Option<usize>,
+        offset: Option<usize>,
+    },
     AddWebsiteAccount {
         account: String,
         password: String,
@@ -27,113 +49,442 @@ pub enum Action {
         new_site_url: String,
         new_note: Option<String>,
     },
+    /// change only the given fields, `null` clears `site_name` or `note`
+    PatchWebsiteAccount {
+        id: i32,
+        account: Option<String>,
+        password: Option<String>,
+        site_url: Option<String>,
+        #[serde(default, deserialize_with = "clearable")]
+        site_name: Option<Option<String>>,
+        #[serde(default, deserialize_with = "clearable")]
+        note: Option<Option<String>>,
+    },
     DeleteWebsiteAccount {
         website_id: i32,
     },
     // check_dead_link
     CheckDeadLink,
+    // connection
+    Logout,
+    Hello {
+        version: u8,
+    },
+    // device
+    EnrollDevice {
+        label: String,
+    },
+    ListDevices,
+    RevokeDevice {
+        device_id: i32,
+    },
+    /// add, change and delete website accounts in one transaction,
+    /// only available over the JSON protocol
+    Batch {
+        actions: Vec<BatchItem>,
+    },
+    /// push every change of the website accounts on this connection from now on,
+    /// until the vault is locked or the session ends
+    Subscribe,
+    // audit_log
+    GetAuditLog {
+        limit: Option<usize>,
+        offset: Option<usize>,
+    },
+    // vault
+    /// wipe the data key, CheckIdentity unlocks the vault again
+    Lock,
+    /// needs the old master password as well as a session
+    ChangeMasterPassword {
+        old_password: String,
+        new_password: String,
+    },
+    // totp
+    /// start the enrollment with a new secret, shown as an otpauth URI
+    EnrollTotp,
+    /// require TOTP once a code of the new secret is right
+    ConfirmTotp {
+        code: String,
+    },
+    /// needs a TOTP or recovery code
+    DisableTotp {
+        code: String,
+    },
+}
+
+/// the name of every action in the JSON protocol, see `Action::name`
+pub const ACTION_NAMES: &[&str] = &[
+    "check_identity",
+    "get_info",
+    "get_website_account_password",
+    "search_website_account",
+    "add_website_account",
+    "change_website_account",
+    "patch_website_account",
+    "delete_website_account",
+    "check_dead_link",
+    "logout",
+    "hello",
+    "enroll_device",
+    "list_devices",
+    "revoke_device",
+    "batch",
+    "subscribe",
+    "get_audit_log",
+    "lock",
+    "change_master_password",
+    "enroll_totp",
+    "confirm_totp",
+    "disable_totp",
+];
+
+impl Action {
+    /// whether the action can be used without a session
+    pub fn is_public(&self) -> bool {
+        matches!(self, Action::CheckIdentity { .. } | Action::Hello { .. })
+    }
+
+    /// name of the action in the audit log, same as in the JSON protocol
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::CheckIdentity { .. } => "check_identity",
+            Action::GetInfo { .. } => "get_info",
+            Action::GetWebsiteAccountPassword { .. } => "get_website_account_password",
+            Action::SearchWebsiteAccount { .. } => "search_website_account",
+            Action::AddWebsiteAccount { .. } => "add_website_account",
+            Action::ChangeWebsiteAccount { .. } => "change_website_account",
+            Action::PatchWebsiteAccount { .. } => "patch_website_account",
+            Action::DeleteWebsiteAccount { .. } => "delete_website_account",
+            Action::CheckDeadLink => "check_dead_link",
+            Action::Logout => "logout",
+            Action::Hello { .. } => "hello",
+            Action::EnrollDevice { .. } => "enroll_device",
+            Action::ListDevices => "list_devices",
+            Action::RevokeDevice { .. } => "revoke_device",
+            Action::Batch { .. } => "batch",
+            Action::Subscribe => "subscribe",
+            Action::GetAuditLog { .. } => "get_audit_log",
+            Action::Lock => "lock",
+            Action::ChangeMasterPassword { .. } => "change_master_password",
+            Action::EnrollTotp => "enroll_totp",
+            Action::ConfirmTotp { .. } => "confirm_totp",
+            Action::DisableTotp { .. } => "disable_totp",
+        }
+    }
+
+    /// whether a success writes its entry of the audit log along with the change,
+    /// in the same transaction, or for CheckIdentity before the vault is unlocked
+    pub fn logs_with_change(&self) -> bool {
+        matches!(
+            self,
+            Action::CheckIdentity { .. }
+                | Action::AddWebsiteAccount { .. }
+                | Action::ChangeWebsiteAccount { .. }
+                | Action::PatchWebsiteAccount { .. }
+                | Action::DeleteWebsiteAccount { .. }
+                | Action::EnrollDevice { .. }
+                | Action::RevokeDevice { .. }
+                | Action::Batch { .. }
+                | Action::ChangeMasterPassword { .. }
+                | Action::EnrollTotp
+                | Action::ConfirmTotp { .. }
+                | Action::DisableTotp { .. }
+        )
+    }
+
+    /// the website account or device the action is about, if any
+    pub fn entry_id(&self) -> Option<i32> {
+        match self {
+            Action::GetWebsiteAccountPassword { website_id }
+            | Action::DeleteWebsiteAccount { website_id } => Some(*website_id),
+            Action::ChangeWebsiteAccount { id, .. } | Action::PatchWebsiteAccount { id, .. } => {
+                Some(*id)
+            }
+            Action::RevokeDevice { device_id } => Some(*device_id),
+            _ => None,
+        }
+    }
+}
+
+/// a present field is `Some`, even if it is `null`,
+/// a missing one is `None` by `#[serde(default)]`
+fn clearable<'de, D>(deserializer: D) -> Result<Option<Option<String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer).map(Some)
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct Request {
+    /// session token, every action except `CheckIdentity` and `Hello` needs one
+    ///
+    /// `None` means the session of the connection is used
+    #[serde(default)]
+    pub token: Option<String>,
+    #[serde(flatten)]
+    pub action: Action,
 }
 
 /// read the request from the socket and return a task
 ///
+/// The request is sent as one frame, see `frame::read_frame`.
+///
 /// Here is the TCP format:
 /// "ACTION\tOTHER_MESSAGE"
 ///
+/// Every action except CheckIdentity and Hello needs the session token
+/// returned by CheckIdentity right after the action:
+/// "ACTION\tTOKEN\tOTHER_MESSAGE"
+///
+/// TOKEN can be left empty once CheckIdentity succeeded
+/// on the same connection.
+///
 /// for example:
 /// - `"0\tmy_password"`
-/// - `"2\tmy_account\tmy_password\tmy_site_url\tmy_site_name\tmy_note"`
+/// - `"2\tmy_token\tmy_account\tmy_password\tmy_site_url\tmy_site_name\tmy_note"`
 ///
 /// ## Here is the list of action:
-/// > - 0: CheckIdentity
-/// > - 1: Login
+/// > - 0: CheckIdentity, `"0\tPASSWORD\tCODE"`, CODE is a TOTP or recovery code,
+/// >   only needed once TOTP is enabled
+/// > - 1: GetInfo, `"1\tTOKEN\tLIMIT\tOFFSET\tSORT"`, LIMIT, OFFSET and SORT are optional,
+/// >   SORT is one of `id`, `name`, `url`, `modified`
 /// > - 2: AddWebsiteAccount
 /// > - 3: ChangeWebsiteAccount
 /// > - 4: DeleteWebsiteAccount
 /// > - 5: CheckDeadLink
+/// > - 6: Logout, the connection is closed afterwards
+/// > - 7: Hello, `"7\tVERSION"` switches the connection to another protocol version
+/// > - 8: EnrollDevice, enroll the TLS client certificate of the connection
+/// > - 9: ListDevices
+/// > - 10: RevokeDevice
+/// > - 11: GetWebsiteAccountPassword, reveal the password of one website account
+/// > - 12: SearchWebsiteAccount, `"12\tTOKEN\tQUERY\tLIMIT\tOFFSET"`, LIMIT and OFFSET are optional
+/// > - 13: GetAuditLog, `"13\tTOKEN\tLIMIT\tOFFSET"`, LIMIT and OFFSET are optional, newest first
+/// > - 14: PatchWebsiteAccount, `"14\tTOKEN\tID\tFIELD=VALUE\tFIELD..."`, only the given fields
+/// >   are changed, a bare `site_name` or `note` clears it, FIELD is one of
+/// >   `account`, `password`, `site_url`, `site_name`, `note`
+/// > - 15: Subscribe, the changes are pushed as Event responses afterwards,
+/// >   in between the responses to the requests
+/// > - 16: ChangeMasterPassword, `"16\tTOKEN\tOLD_PASSWORD\tNEW_PASSWORD"`,
+/// >   only for the `master_password` backend
+/// > - 17: Lock, wipe the data key until the next CheckIdentity
+/// > - 18: EnrollTotp, a new secret which is required once it is confirmed
+/// > - 19: ConfirmTotp, `"19\tTOKEN\tCODE"`, the recovery codes are returned
+/// > - 20: DisableTotp, `"20\tTOKEN\tCODE"`, CODE is a TOTP or recovery code
 ///
-pub async fn read_request(stream: &TcpStream) -> Result<Action, Box<dyn Error>> {
-    stream.readable().await?;
-    let mut buffer = [0; 4096];
-    match stream.try_read(&mut buffer) {
-        Ok(0) => Err("Failed to read from socket".into()),
-        Ok(_) => {
-            let request = String::from_utf8_lossy(&buffer);
-            let mut parts: Vec<&str> = request.split('\t').collect();
-
-            // eprintln!("request: {}", request);
-            let mut result = vec![];
-            for part in &mut parts {
-                result.push(part.trim_end_matches('\0'));
-            }
-            // eprintln!("result: {:?}", result);
-
-            Ok(pack_action(result)?)
-        }
-        Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => Err("Blocked".into()),
-        Err(e) => Err(e.into()),
+/// Once the connection is switched to version 2 the request is a JSON object,
+/// see `json::parse_request`. `batch` is only available there.
+///
+/// return `None` if the peer closed the connection
+pub async fn read_request<R>(
+    stream: &mut R,
+    version: u8,
+) -> io::Result<Option<Result<Request, ProError>>>
+where
+    R: AsyncRead + Unpin,
+{
+    let frame = match read_frame(stream).await? {
+        Some(frame) => frame,
+        None => return Ok(None),
+    };
+
+    if version == PROTOCOL_V2 {
+        return Ok(Some(parse_request(&frame)));
     }
+
+    let request = match String::from_utf8(frame) {
+        Ok(request) => request,
+        Err(e) => return Ok(Some(Err(ProError::ParseError(e.to_string())))),
+    };
+    let parts: Vec<&str> = request.split('\t').collect();
+
+    Ok(Some(pack_action(parts)))
 }
 
-fn pack_action(parts: Vec<&str>) -> Result<Action, Box<dyn Error>> {
-    let action = parts[0].trim_end_matches('\0');
-    let action = action.parse::<i32>()?;
+fn pack_action(parts: Vec<&str>) -> Result<Request, ProError> {
+    let action = parts[0].parse::<i32>()?;
+
+    // CheckIdentity and Hello are the only actions without a token
+    let (token, parts) = if action == 0 || action == 7 {
+        (None, &parts[1..])
+    } else {
+        let token = parts.get(1).ok_or("Session token is missing")?;
+        let token = Some(token.to_string()).filter(|t| !t.is_empty());
+        (token, &parts[2.min(parts.len())..])
+    };
 
     // for part in &parts {
     //     eprintln!("part in pack_action: {}", part);
     // }
     // eprintln!("parts[0]: {:?}", parts[0]);
 
-    match action {
+    let action = match action {
         0 => {
-            let password = parts.get(1).ok_or("Password is missing")?.to_string();
-            Ok(Action::CheckIdentity { password })
+            let password = parts.first().ok_or("Password is missing")?.to_string();
+            let totp = parts
+                .get(1)
+                .map(|s| s.to_string())
+                .filter(|s| !s.is_empty());
+            Action::CheckIdentity { password, totp }
+        }
+        1 => {
+            let limit = parse_optional(parts.first())?;
+            let offset = parse_optional(parts.get(1))?;
+            let sort = parse_optional(parts.get(2))?;
+            Action::GetInfo {
+                limit,
+                offset,
+                sort,
+            }
         }
-        1 => Ok(Action::GetInfo),
         2 => {
-            let account = parts.get(1).ok_or("Account is missing")?.to_string();
-            let password = parts.get(2).ok_or("Password is missing")?.to_string();
-            let site_url = parts.get(3).ok_or("Site URL is missing")?.to_string();
-            let site_name = parts.get(4).map(|s| s.to_string());
-            let note = parts.get(5).map(|s| s.to_string());
-            Ok(Action::AddWebsiteAccount {
+            let account = parts.first().ok_or("Account is missing")?.to_string();
+            let password = parts.get(1).ok_or("Password is missing")?.to_string();
+            let site_url = parts.get(2).ok_or("Site URL is missing")?.to_string();
+            let site_name = parts.get(3).map(|s| s.to_string());
+            let note = parts.get(4).map(|s| s.to_string());
+            Action::AddWebsiteAccount {
                 account,
                 password,
                 site_url,
                 site_name,
                 note,
-            })
+            }
         }
         3 => {
             let id = parts
-                .get(1)
+                .first()
                 .ok_or("Website id is missing")?
                 .parse::<i32>()?;
-            let new_account = parts.get(2).ok_or("Account is missing")?.to_string();
-            let new_password = parts.get(3).ok_or("Password is missing")?.to_string();
-            let new_site_name = parts.get(4).map(|s| s.to_string());
-            let new_site_url = parts.get(5).ok_or("Site URL is missing")?.to_string();
-            let new_note = parts.get(6).map(|s| s.to_string());
-            Ok(Action::ChangeWebsiteAccount {
+            let new_account = parts.get(1).ok_or("Account is missing")?.to_string();
+            let new_password = parts.get(2).ok_or("Password is missing")?.to_string();
+            let new_site_name = parts.get(3).map(|s| s.to_string());
+            let new_site_url = parts.get(4).ok_or("Site URL is missing")?.to_string();
+            let new_note = parts.get(5).map(|s| s.to_string());
+            Action::ChangeWebsiteAccount {
                 id,
                 new_account,
                 new_password,
                 new_site_name,
                 new_site_url,
                 new_note,
-            })
+            }
         }
         4 => {
             let website_id = parts
-                .get(1)
+                .first()
+                .ok_or("Website id is missing")?
+                .parse::<i32>()?;
+            Action::DeleteWebsiteAccount { website_id }
+        }
+        5 => Action::CheckDeadLink,
+        6 => Action::Logout,
+        7 => {
+            let version = parts.first().ok_or("Version is missing")?.parse::<u8>()?;
+            Action::Hello { version }
+        }
+        8 => {
+            let label = parts.first().ok_or("Label is missing")?.to_string();
+            Action::EnrollDevice { label }
+        }
+        9 => Action::ListDevices,
+        10 => {
+            let device_id = parts
+                .first()
+                .ok_or("Device id is missing")?
+                .parse::<i32>()?;
+            Action::RevokeDevice { device_id }
+        }
+        11 => {
+            let website_id = parts
+                .first()
+                .ok_or("Website id is missing")?
+                .parse::<i32>()?;
+            Action::GetWebsiteAccountPassword { website_id }
+        }
+        12 => {
+            let query = parts.first().ok_or("Query is missing")?.to_string();
+            let limit = parse_optional(parts.get(1))?;
+            let offset = parse_optional(parts.get(2))?;
+            Action::SearchWebsiteAccount {
+                query,
+                limit,
+                offset,
+            }
+        }
+        13 => {
+            let limit = parse_optional(parts.first())?;
+            let offset = parse_optional(parts.get(1))?;
+            Action::GetAuditLog { limit, offset }
+        }
+        14 => {
+            let id = parts
+                .first()
                 .ok_or("Website id is missing")?
                 .parse::<i32>()?;
-            Ok(Action::DeleteWebsiteAccount { website_id })
+
+            let (mut account, mut password, mut site_url) = (None, None, None);
+            let (mut site_name, mut note) = (None, None);
+            for field in parts.get(1..).unwrap_or_default() {
+                match field.split_once('=') {
+                    Some(("account", value)) => account = Some(value.to_string()),
+                    Some(("password", value)) => password = Some(value.to_string()),
+                    Some(("site_url", value)) => site_url = Some(value.to_string()),
+                    Some(("site_name", value)) => site_name = Some(Some(value.to_string())),
+                    Some(("note", value)) => note = Some(Some(value.to_string())),
+                    None if *field == "site_name" => site_name = Some(None),
+                    None if *field == "note" => note = Some(None),
+                    _ => return Err(format!("Invalid field: {}", field).into()),
+                }
+            }
+
+            Action::PatchWebsiteAccount {
+                id,
+                account,
+                password,
+                site_url,
+                site_name,
+                note,
+            }
+        }
+        15 => Action::Subscribe,
+        16 => {
+            let old_password = parts.first().ok_or("Old password is missing")?.to_string();
+            let new_password = parts.get(1).ok_or("New password is missing")?.to_string();
+            Action::ChangeMasterPassword {
+                old_password,
+                new_password,
+            }
+        }
+        17 => Action::Lock,
+        18 => Action::EnrollTotp,
+        19 => {
+            let code = parts.first().ok_or("Code is missing")?.to_string();
+            Action::ConfirmTotp { code }
         }
-        5 => Ok(Action::CheckDeadLink),
-        _ => {
-            eprintln!("Invalid Action: {}", action);
-            Err("Invalid Action".into())
+        20 => {
+            let code = parts.first().ok_or("Code is missing")?.to_string();
+            Action::DisableTotp { code }
         }
+        _ => return Err(ProError::UnknownAction(action.to_string())),
+    };
+
+    Ok(Request { token, action })
+}
+
+/// an optional number, missing or empty means `None`
+fn parse_optional<T>(part: Option<&&str>) -> Result<Option<T>, ProError>
+where
+    T: std::str::FromStr,
+    T::Err: Display,
+{
+    match part {
+        Some(part) if !part.is_empty() => part
+            .parse::<T>()
+            .map(Some)
+            .map_err(|e| ProError::ParseError(e.to_string())),
+        _ => Ok(None),
     }
 }
 
@@ -144,27 +495,67 @@ mod tests {
     #[test]
     fn test_pack_action() {
         let parts = vec!["0", "my_password"];
-        let action = pack_action(parts).unwrap();
+        let request = pack_action(parts).unwrap();
+        assert_eq!(request.token, None);
+        assert_eq!(
+            request.action,
+            Action::CheckIdentity {
+                password: "my_password".to_string(),
+                totp: None,
+            }
+        );
+
+        let action = pack_action(vec!["0", "my_password", "123456"])
+            .unwrap()
+            .action;
         assert_eq!(
             action,
             Action::CheckIdentity {
-                password: "my_password".to_string()
+                password: "my_password".to_string(),
+                totp: Some("123456".to_string()),
+            }
+        );
+
+        let parts = vec!["1", "my_token"];
+        let request = pack_action(parts).unwrap();
+        assert_eq!(request.token, Some("my_token".to_string()));
+        assert_eq!(
+            request.action,
+            Action::GetInfo {
+                limit: None,
+                offset: None,
+                sort: None
+            }
+        );
+
+        let parts = vec!["1", "my_token", "20", "40", "name"];
+        let action = pack_action(parts).unwrap().action;
+        assert_eq!(
+            action,
+            Action::GetInfo {
+                limit: Some(20),
+                offset: Some(40),
+                sort: Some(SortKey::Name)
             }
         );
 
+        let parts = vec!["1", ""];
+        let request = pack_action(parts).unwrap();
+        assert_eq!(request.token, None);
+
         let parts = vec!["1"];
-        let action = pack_action(parts).unwrap();
-        assert_eq!(action, Action::GetInfo);
+        assert!(pack_action(parts).is_err());
 
         let parts = vec![
             "2",
+            "my_token",
             "my_account",
             "my_password",
             "my_site_url",
             "my_site_name",
             "my_note",
         ];
-        let action = pack_action(parts).unwrap();
+        let action = pack_action(parts).unwrap().action;
         assert_eq!(
             action,
             Action::AddWebsiteAccount {
@@ -178,6 +569,7 @@ mod tests {
 
         let parts = vec![
             "3",
+            "my_token",
             "1",
             "my_account",
             "my_password",
@@ -185,7 +577,7 @@ mod tests {
             "my_site_url",
             "my_note",
         ];
-        let action = pack_action(parts).unwrap();
+        let action = pack_action(parts).unwrap().action;
         assert_eq!(
             action,
             Action::ChangeWebsiteAccount {
@@ -198,12 +590,135 @@ mod tests {
             }
         );
 
-        let parts = vec!["4", "1"];
-        let action = pack_action(parts).unwrap();
+        let parts = vec!["4", "my_token", "1"];
+        let action = pack_action(parts).unwrap().action;
         assert_eq!(action, Action::DeleteWebsiteAccount { website_id: 1 });
 
-        let parts = vec!["5"];
-        let action = pack_action(parts).unwrap();
+        let parts = vec!["5", "my_token"];
+        let action = pack_action(parts).unwrap().action;
         assert_eq!(action, Action::CheckDeadLink);
+
+        let parts = vec!["6", "my_token"];
+        let action = pack_action(parts).unwrap().action;
+        assert_eq!(action, Action::Logout);
+
+        let parts = vec!["7", "2"];
+        let request = pack_action(parts).unwrap();
+        assert_eq!(request.token, None);
+        assert_eq!(request.action, Action::Hello { version: 2 });
+
+        let parts = vec!["8", "my_token", "my_laptop"];
+        let action = pack_action(parts).unwrap().action;
+        assert_eq!(
+            action,
+            Action::EnrollDevice {
+                label: "my_laptop".to_string()
+            }
+        );
+
+        let parts = vec!["9", "my_token"];
+        let action = pack_action(parts).unwrap().action;
+        assert_eq!(action, Action::ListDevices);
+
+        let parts = vec!["10", "my_token", "1"];
+        let action = pack_action(parts).unwrap().action;
+        assert_eq!(action, Action::RevokeDevice { device_id: 1 });
+
+        let parts = vec!["11", "my_token", "1"];
+        let action = pack_action(parts).unwrap().action;
+        assert_eq!(action, Action::GetWebsiteAccountPassword { website_id: 1 });
+
+        let parts = vec!["12", "my_token", "example.com", "10"];
+        let action = pack_action(parts).unwrap().action;
+        assert_eq!(
+            action,
+            Action::SearchWebsiteAccount {
+                query: "example.com".to_string(),
+                limit: Some(10),
+                offset: None,
+            }
+        );
+
+        let parts = vec!["13", "my_token", "", "20"];
+        let action = pack_action(parts).unwrap().action;
+        assert_eq!(
+            action,
+            Action::GetAuditLog {
+                limit: None,
+                offset: Some(20),
+            }
+        );
+        assert_eq!(action.name(), "get_audit_log");
+
+        let parts = vec!["14", "my_token", "3", "note=bmV3", "site_name"];
+        let action = pack_action(parts).unwrap().action;
+        assert_eq!(
+            action,
+            Action::PatchWebsiteAccount {
+                id: 3,
+                account: None,
+                password: None,
+                site_url: None,
+                site_name: Some(None),
+                note: Some(Some("bmV3".to_string())),
+            }
+        );
+
+        let parts = vec!["15", ""];
+        let request = pack_action(parts).unwrap();
+        assert_eq!(request.token, None);
+        assert_eq!(request.action, Action::Subscribe);
+
+        // account can't be cleared
+        assert!(matches!(
+            pack_action(vec!["14", "my_token", "3", "account"]),
+            Err(ProError::ParseError(_))
+        ));
+
+        assert!(matches!(
+            pack_action(vec!["4", "my_token", "not_a_number"]),
+            Err(ProError::ParseError(_))
+        ));
+        let parts = vec!["16", "my_token", "old", "new"];
+        let action = pack_action(parts).unwrap().action;
+        assert_eq!(
+            action,
+            Action::ChangeMasterPassword {
+                old_password: "old".to_string(),
+                new_password: "new".to_string(),
+            }
+        );
+        assert!(pack_action(vec!["16", "my_token", "old"]).is_err());
+
+        let action = pack_action(vec!["17", "my_token"]).unwrap().action;
+        assert_eq!(action, Action::Lock);
+        assert_eq!(action.name(), "lock");
+
+        let action = pack_action(vec!["18", "my_token"]).unwrap().action;
+        assert_eq!(action, Action::EnrollTotp);
+        let action = pack_action(vec!["19", "my_token", "123456"])
+            .unwrap()
+            .action;
+        assert_eq!(
+            action,
+            Action::ConfirmTotp {
+                code: "123456".to_string()
+            }
+        );
+        let action = pack_action(vec!["20", "my_token", "abcde-fghij"])
+            .unwrap()
+            .action;
+        assert_eq!(
+            action,
+            Action::DisableTotp {
+                code: "abcde-fghij".to_string()
+            }
+        );
+        assert!(pack_action(vec!["20", "my_token"]).is_err());
+
+        assert!(matches!(
+            pack_action(vec!["99", "my_token"]),
+            Err(ProError::UnknownAction(action)) if action == "99"
+        ));
     }
 }
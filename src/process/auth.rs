@@ -1,13 +1,226 @@
-use pam::Authenticator;
-use pam::PamResult;
+use std::fmt;
+use std::sync::{Arc, RwLock};
 
-pub async fn authourize(password: String) -> PamResult<()>{
-    let mut auth = Authenticator::with_password("login")?;
-    let username = whoami::username();
-    auth.get_handler().set_credentials(username, password);
-    auth.authenticate()
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{self, PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use pam::Authenticator as PamClient;
+
+use crate::config::AuthConfig;
+use crate::db::{Db, DbError};
+
+/// Why the password was not accepted
+#[derive(Debug)]
+pub enum AuthError {
+    /// the password was checked and refused
+    Rejected,
+    /// the backend could not check the password
+    Unavailable(String),
+    /// the password of the backend can't be changed through the vault
+    Unsupported,
+}
+
+impl From<DbError> for AuthError {
+    fn from(e: DbError) -> Self {
+        AuthError::Unavailable(e.to_string())
+    }
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::Rejected => write!(f, "Wrong password"),
+            AuthError::Unavailable(e) => write!(f, "Authentication is unavailable: {}", e),
+            AuthError::Unsupported => write!(f, "The password can't be changed here"),
+        }
+    }
+}
+
+/// Checks the password of CheckIdentity
+///
+/// The check may block, it is run on a blocking thread.
+pub trait Authenticator: Send + Sync {
+    /// the user the session is created for if the password is right
+    fn authenticate(&self, password: &str) -> Result<String, AuthError>;
+
+    /// the hash of `new_password` to store, if `old_password` is right
+    ///
+    /// Only backends which keep the password in the vault support it.
+    fn hash_new_password(
+        &self,
+        _old_password: &str,
+        _new_password: &str,
+    ) -> Result<String, AuthError> {
+        Err(AuthError::Unsupported)
+    }
+
+    /// use the hash of `hash_new_password` once it is stored
+    fn set_password_hash(&self, _hash: String) {}
+
+    /// whether the data key is derived from this password, see `Db::unlock`,
+    /// otherwise it is the legacy `KEY`
+    fn derives_key(&self) -> bool {
+        false
+    }
+}
+
+/// build the backend chosen by the config
+pub async fn from_config(
+    config: &AuthConfig,
+    db: &Db,
+) -> Result<Arc<dyn Authenticator>, AuthError> {
+    Ok(match config {
+        AuthConfig::Pam { service, user } => Arc::new(PamAuthenticator {
+            service: service.clone(),
+            user: user.clone(),
+        }),
+        AuthConfig::MasterPassword { initial_hash } => {
+            let hash = stored_master_password(db, initial_hash.as_deref()).await?;
+            Arc::new(MasterPasswordAuthenticator::new(&hash)?)
+        }
+        #[cfg(feature = "mock-auth")]
+        AuthConfig::Mock { password } => Arc::new(MockAuthenticator::new(password)),
+    })
+}
+
+/// the hash of the master password in the database,
+/// on the first run it is set to `initial_hash` or asked for on the terminal
+async fn stored_master_password(db: &Db, initial_hash: Option<&str>) -> Result<String, AuthError> {
+    if let Some(hash) = db.get_master_password_hash().await? {
+        return Ok(hash);
+    }
+
+    let hash = match initial_hash {
+        Some(hash) => hash.to_string(),
+        None => {
+            let prompt = |prompt| {
+                rpassword::prompt_password(prompt).map_err(|e| {
+                    AuthError::Unavailable(format!(
+                        "No master password is set yet, run in a terminal or set MASTER_PASSWORD_HASH: {}",
+                        e
+                    ))
+                })
+            };
+            let password = prompt("Set the master password: ")?;
+            if password.is_empty() || password != prompt("Repeat the master password: ")? {
+                return Err(AuthError::Unavailable(
+                    "The master passwords are empty or do not match".to_string(),
+                ));
+            }
+            hash_password(&password).map_err(|e| AuthError::Unavailable(e.to_string()))?
+        }
+    };
+    // check it before it is stored
+    MasterPasswordAuthenticator::new(&hash)?;
+
+    db.set_master_password_hash(hash.clone()).await?;
+    eprintln!("The master password is set");
+    Ok(hash)
+}
+
+/// the password of a local user, checked by PAM
+pub struct PamAuthenticator {
+    pub service: String,
+    pub user: String,
+}
+
+impl Default for PamAuthenticator {
+    fn default() -> Self {
+        PamAuthenticator {
+            service: "login".to_string(),
+            user: whoami::username(),
+        }
+    }
+}
+
+impl Authenticator for PamAuthenticator {
+    fn authenticate(&self, password: &str) -> Result<String, AuthError> {
+        let mut auth = PamClient::with_password(&self.service)
+            .map_err(|e| AuthError::Unavailable(e.to_string()))?;
+        auth.get_handler()
+            .set_credentials(self.user.clone(), password.to_string());
+        auth.authenticate().map_err(|_| AuthError::Rejected)?;
+        Ok(self.user.clone())
+    }
 }
 
+/// a password of the vault alone, checked against an Argon2 hash
+pub struct MasterPasswordAuthenticator {
+    hash: RwLock<String>,
+}
+
+impl MasterPasswordAuthenticator {
+    /// `hash` is a PHC string like `$argon2id$v=19$m=19456,t=2,p=1$SALT$HASH`
+    pub fn new(hash: &str) -> Result<Self, AuthError> {
+        PasswordHash::new(hash).map_err(|e| AuthError::Unavailable(e.to_string()))?;
+        Ok(MasterPasswordAuthenticator {
+            hash: RwLock::new(hash.to_string()),
+        })
+    }
+}
+
+impl Authenticator for MasterPasswordAuthenticator {
+    fn authenticate(&self, password: &str) -> Result<String, AuthError> {
+        let hash = self.hash.read().unwrap_or_else(|e| e.into_inner());
+        let hash = PasswordHash::new(&hash).map_err(|e| AuthError::Unavailable(e.to_string()))?;
+        match Argon2::default().verify_password(password.as_bytes(), &hash) {
+            Ok(()) => Ok(whoami::username()),
+            Err(password_hash::Error::Password) => Err(AuthError::Rejected),
+            Err(e) => Err(AuthError::Unavailable(e.to_string())),
+        }
+    }
+
+    fn hash_new_password(
+        &self,
+        old_password: &str,
+        new_password: &str,
+    ) -> Result<String, AuthError> {
+        self.authenticate(old_password)?;
+        hash_password(new_password).map_err(|e| AuthError::Unavailable(e.to_string()))
+    }
+
+    fn set_password_hash(&self, hash: String) {
+        *self.hash.write().unwrap_or_else(|e| e.into_inner()) = hash;
+    }
+
+    fn derives_key(&self) -> bool {
+        true
+    }
+}
+
+/// hash a password with Argon2id and a random salt, as a PHC string
+pub fn hash_password(password: &str) -> Result<String, password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &salt)?
+        .to_string())
+}
+
+/// accepts one fixed password, only meant for tests
+#[cfg(any(test, feature = "mock-auth"))]
+pub struct MockAuthenticator {
+    password: String,
+}
+
+#[cfg(any(test, feature = "mock-auth"))]
+impl MockAuthenticator {
+    pub fn new(password: &str) -> Self {
+        MockAuthenticator {
+            password: password.to_string(),
+        }
+    }
+}
+
+#[cfg(any(test, feature = "mock-auth"))]
+impl Authenticator for MockAuthenticator {
+    fn authenticate(&self, password: &str) -> Result<String, AuthError> {
+        if password == self.password {
+            Ok(whoami::username())
+        } else {
+            Err(AuthError::Rejected)
+        }
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -17,8 +230,48 @@ mod tests {
         dotenv::dotenv().ok();
         let password = std::env::var("PAM_PASSWORD").unwrap();
 
-        let result = authourize(password).await;
+        let result = PamAuthenticator::default().authenticate(&password);
 
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn test_master_password() {
+        let hash = hash_password("correct horse").unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+
+        let auth = MasterPasswordAuthenticator::new(&hash).unwrap();
+        assert!(auth.authenticate("correct horse").is_ok());
+        assert!(matches!(
+            auth.authenticate("battery staple"),
+            Err(AuthError::Rejected)
+        ));
+
+        assert!(MasterPasswordAuthenticator::new("not a hash").is_err());
+
+        // the old password is still used until the new hash is set
+        assert!(matches!(
+            auth.hash_new_password("battery staple", "new"),
+            Err(AuthError::Rejected)
+        ));
+        let new_hash = auth.hash_new_password("correct horse", "new").unwrap();
+        assert!(auth.authenticate("correct horse").is_ok());
+        auth.set_password_hash(new_hash);
+        assert!(auth.authenticate("new").is_ok());
+        assert!(auth.authenticate("correct horse").is_err());
+    }
+
+    #[test]
+    fn test_mock() {
+        let auth = MockAuthenticator::new("secret");
+        assert!(auth.authenticate("secret").is_ok());
+        assert!(matches!(
+            auth.authenticate("nope"),
+            Err(AuthError::Rejected)
+        ));
+        assert!(matches!(
+            auth.hash_new_password("secret", "new"),
+            Err(AuthError::Unsupported)
+        ));
+    }
 }
@@ -0,0 +1,91 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::db::Db;
+
+/// Locks the vault again once it was not used for a while
+///
+/// Locking wipes the data key from memory, CheckIdentity unlocks the vault again.
+pub struct AutoLock {
+    db: Arc<Db>,
+    /// `None` never locks the vault by itself
+    idle: Option<Duration>,
+    last_used: Mutex<Instant>,
+}
+
+impl AutoLock {
+    pub fn new(db: Arc<Db>, idle: Option<Duration>) -> Self {
+        AutoLock {
+            db,
+            idle,
+            last_used: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// the vault was used just now
+    pub fn touch(&self) {
+        *self.last_used.lock().unwrap_or_else(|e| e.into_inner()) = Instant::now();
+    }
+
+    fn idle_for(&self) -> Duration {
+        self.last_used
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .elapsed()
+    }
+
+    /// lock the vault whenever it was idle for too long, never returns
+    pub async fn run(self: Arc<Self>) {
+        let Some(idle) = self.idle else {
+            return std::future::pending().await;
+        };
+
+        loop {
+            let idle_for = self.idle_for();
+            if idle_for < idle {
+                tokio::time::sleep(idle - idle_for).await;
+                continue;
+            }
+
+            if !self.db.is_locked() {
+                self.db.lock();
+                eprintln!(
+                    "The vault was idle for {} seconds and is locked",
+                    idle.as_secs()
+                );
+            }
+            // Nothing to do before the next use, which is at least a period away
+            tokio::time::sleep(idle).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encrypt::DataKey;
+
+    // the clock only moves when every task waits, so the order is always the same
+    #[tokio::test(start_paused = true)]
+    async fn test_auto_lock() {
+        let db = Arc::new(Db::in_memory("test_auto_lock"));
+        db.set_data_key(Some(DataKey::generate()));
+
+        let idle = Duration::from_secs(300);
+        let auto_lock = Arc::new(AutoLock::new(db.clone(), Some(idle)));
+        tokio::spawn(auto_lock.clone().run());
+
+        tokio::time::sleep(Duration::from_secs(200)).await;
+        assert!(!db.is_locked());
+        auto_lock.touch();
+
+        // still in use, 200s after the last use
+        tokio::time::sleep(Duration::from_secs(200)).await;
+        assert!(!db.is_locked());
+
+        tokio::time::sleep(Duration::from_secs(150)).await;
+        assert!(db.is_locked());
+    }
+}
@@ -50,10 +50,10 @@ pub async fn check_dead_link_info(
                     WebsiteAccountWithDeadLink {
                         id: account.id,
                         account: account.account,
-                        password: account.password,
                         site_url: account.site_url,
                         site_name: account.site_name,
                         note: account.note,
+                        updated_at: account.updated_at,
                         dead_link: !response.status().is_success(),
                     }
                 }
@@ -62,10 +62,10 @@ pub async fn check_dead_link_info(
                     WebsiteAccountWithDeadLink {
                         id: account.id,
                         account: account.account,
-                        password: account.password,
                         site_url: account.site_url,
                         site_name: account.site_name,
                         note: account.note,
+                        updated_at: account.updated_at,
                         dead_link: true,
                     }
                 }
@@ -122,6 +122,7 @@ mod tests {
             site_url: "https://www.baidu.com".to_string(),
             site_name: Some("baidu".to_string()),
             note: Some("nothing".to_string()),
+            updated_at: 0,
         };
         let account2 = WebsiteAccount {
             id: Some(2),
@@ -130,6 +131,7 @@ mod tests {
             site_url: "https://www.baidu.com".to_string(),
             site_name: Some("baidu".to_string()),
             note: Some("nothing".to_string()),
+            updated_at: 0,
         };
         let account3 = WebsiteAccount {
             id: Some(3),
@@ -138,6 +140,7 @@ mod tests {
             site_url: "https://www.not_exist.not_exist".to_string(),
             site_name: Some("baidu".to_string()),
             note: Some("nothing".to_string()),
+            updated_at: 0,
         };
         list.push(account1);
         list.push(account2);
@@ -0,0 +1,53 @@
+/// The tab separated protocol, every connection starts with it
+pub const PROTOCOL_V1: u8 = 1;
+/// The JSON protocol
+pub const PROTOCOL_V2: u8 = 2;
+
+use tokio::sync::broadcast;
+
+use crate::db::event::Event;
+use crate::db::models::Device;
+
+/// Who is on the other side of the connection
+#[derive(Clone, Default)]
+pub struct Peer {
+    /// IP address of the peer, or `unix:UID` for the unix socket
+    pub addr: String,
+    /// fingerprint of the TLS client certificate, see `tls::fingerprint`
+    pub cert_fingerprint: Option<String>,
+}
+
+/// State kept for one connection across its requests
+pub struct Connection {
+    pub peer: Peer,
+    /// enrolled device of the client certificate, checked for every request
+    pub device: Option<Device>,
+    /// session token obtained by CheckIdentity on this connection
+    pub token: Option<String>,
+    /// user of the session
+    pub user: Option<String>,
+    /// protocol version spoken on this connection, changed by `Hello`
+    pub version: u8,
+    /// changes pushed to the peer, set by `Subscribe`
+    pub events: Option<broadcast::Receiver<Event>>,
+}
+
+impl Connection {
+    pub fn new(peer: Peer) -> Self {
+        Connection {
+            peer,
+            device: None,
+            token: None,
+            user: None,
+            version: PROTOCOL_V1,
+            events: None,
+        }
+    }
+
+    /// forget the session of this connection, and the subscription with it
+    pub fn logout(&mut self) -> Option<String> {
+        self.user = None;
+        self.events = None;
+        self.token.take()
+    }
+}
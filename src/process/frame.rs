@@ -0,0 +1,87 @@
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// The biggest frame we are willing to read
+pub const MAX_FRAME_LEN: usize = 1024 * 1024;
+
+/// read one frame from the stream
+///
+/// Here is the frame format:
+/// "LENGTH PAYLOAD"
+///
+/// LENGTH is a big-endian u32 with the length of PAYLOAD in bytes.
+///
+/// return `None` if the peer closed the connection before a new frame
+pub async fn read_frame<R>(reader: &mut R) -> io::Result<Option<Vec<u8>>>
+where
+    R: AsyncRead + Unpin,
+{
+    let len = match reader.read_u32().await {
+        Ok(len) => len as usize,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Frame of {} bytes is too large", len),
+        ));
+    }
+
+    let mut payload = vec![0; len];
+    reader.read_exact(&mut payload).await?;
+
+    Ok(Some(payload))
+}
+
+/// write one frame to the stream, see `read_frame` for the format
+pub async fn write_frame<W>(writer: &mut W, payload: &[u8]) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let len = u32::try_from(payload.len())
+        .ok()
+        .filter(|len| *len as usize <= MAX_FRAME_LEN)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Frame is too large"))?;
+
+    writer.write_u32(len).await?;
+    writer.write_all(payload).await?;
+    writer.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_frame() {
+        // a tiny buffer forces the frames to be split
+        let (mut client, mut server) = tokio::io::duplex(8);
+
+        let note = "a\tlong\nnote ".repeat(1000);
+        let sent = note.clone();
+        let writer = tokio::spawn(async move {
+            write_frame(&mut client, sent.as_bytes()).await.unwrap();
+            write_frame(&mut client, b"").await.unwrap();
+        });
+
+        let frame = read_frame(&mut server).await.unwrap().unwrap();
+        assert_eq!(frame, note.as_bytes());
+
+        let frame = read_frame(&mut server).await.unwrap().unwrap();
+        assert!(frame.is_empty());
+
+        writer.await.unwrap();
+        assert!(read_frame(&mut server).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_frame_too_large() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+
+        client.write_u32(MAX_FRAME_LEN as u32 + 1).await.unwrap();
+        assert!(read_frame(&mut server).await.is_err());
+    }
+}
@@ -0,0 +1,259 @@
+use serde_json::{json, Value};
+
+use super::action::{Request, ACTION_NAMES};
+use super::process_result::{ProError, ProOk};
+
+/// parse a request of the JSON protocol
+///
+/// Here is the format:
+/// `{"token": "my_token", "action": "ACTION", ...OTHER_FIELDS}`
+///
+/// for example:
+/// - `{"action": "check_identity", "password": "my_password"}`
+/// - `{"token": "my_token", "action": "get_info"}`
+/// - `{"action": "delete_website_account", "website_id": 1}`
+///
+/// `token` can be left out once CheckIdentity succeeded on the same connection.
+pub fn parse_request(frame: &[u8]) -> Result<Request, ProError> {
+    let request: Value =
+        serde_json::from_slice(frame).map_err(|e| ProError::ParseError(e.to_string()))?;
+
+    // An action which does not exist is told apart from a malformed one
+    if let Some(action) = request.get("action").and_then(Value::as_str) {
+        if !ACTION_NAMES.contains(&action) {
+            return Err(ProError::UnknownAction(action.to_string()));
+        }
+    }
+
+    serde_json::from_value(request).map_err(|e| ProError::ParseError(e.to_string()))
+}
+
+/// build a response of the JSON protocol
+///
+/// Here is the format:
+/// - `{"ok": true, "code": CODE, "data": DATA}`
+/// - `{"ok": false, "code": CODE, "error": {"kind": KIND, "message": MESSAGE}}`
+///
+/// CODE is the same code as the tab separated protocol uses.
+/// A `locked_out` error has `"retry_after": SECONDS` in `error` as well,
+/// a `batch_error` has the `"index"` of the failed item.
+pub fn json_response(result: Result<ProOk, ProError>) -> String {
+    let response = match result {
+        Ok(ok) => {
+            let code = ok.code();
+            let data = match ok {
+                ProOk::Ack => Value::Null,
+                ProOk::Session { token, expires_in } => json!({
+                    "token": token,
+                    "expires_in": expires_in,
+                }),
+                ProOk::Info { total, list } => json!({ "total": total, "accounts": list }),
+                ProOk::Devices(list) => json!(list),
+                ProOk::Password(password) => json!({ "password": password }),
+                ProOk::TotpUri(uri) => json!({ "uri": uri }),
+                ProOk::RecoveryCodes(codes) => json!({ "recovery_codes": codes }),
+                ProOk::Batch(ids) => json!(ids),
+                ProOk::Event(event) => json!(event),
+                ProOk::AuditLog { total, valid, list } => {
+                    json!({ "total": total, "valid": valid, "entries": list })
+                }
+                ProOk::DeadLink(list) => list
+                    .into_iter()
+                    .map(|(id, dead_link)| json!({ "id": id, "dead_link": dead_link }))
+                    .collect(),
+            };
+            json!({ "ok": true, "code": code, "data": data })
+        }
+        Err(e) => {
+            let mut error = json!({
+                "kind": e.kind(),
+                "message": e.to_string(),
+            });
+            match &e {
+                ProError::LockedOut(retry_after) => {
+                    error["retry_after"] = json!(retry_after.as_secs())
+                }
+                ProError::BatchError(batch) => error["index"] = json!(batch.index),
+                _ => {}
+            }
+            json!({ "ok": false, "code": e.code(), "error": error })
+        }
+    };
+
+    response.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::event::Event;
+    use crate::db::{DbError, SortKey};
+    use crate::process::action::Action;
+
+    #[test]
+    fn test_parse_request() {
+        let request =
+            parse_request(br#"{"action": "check_identity", "password": "my\tpass\nword"}"#)
+                .unwrap();
+        assert_eq!(request.token, None);
+        assert_eq!(
+            request.action,
+            Action::CheckIdentity {
+                password: "my\tpass\nword".to_string(),
+                totp: None,
+            }
+        );
+
+        let request =
+            parse_request(br#"{"action": "check_identity", "password": "p", "totp": "123456"}"#)
+                .unwrap();
+        assert_eq!(
+            request.action,
+            Action::CheckIdentity {
+                password: "p".to_string(),
+                totp: Some("123456".to_string()),
+            }
+        );
+
+        let request = parse_request(br#"{"token": "my_token", "action": "get_info"}"#).unwrap();
+        assert_eq!(request.token, Some("my_token".to_string()));
+        assert_eq!(
+            request.action,
+            Action::GetInfo {
+                limit: None,
+                offset: None,
+                sort: None
+            }
+        );
+
+        let request =
+            parse_request(br#"{"action": "get_info", "limit": 20, "sort": "modified"}"#).unwrap();
+        assert_eq!(
+            request.action,
+            Action::GetInfo {
+                limit: Some(20),
+                offset: None,
+                sort: Some(SortKey::Modified)
+            }
+        );
+
+        let request = parse_request(
+            br#"{"action": "add_website_account", "account": "a", "password": "p", "site_url": "u"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            request.action,
+            Action::AddWebsiteAccount {
+                account: "a".to_string(),
+                password: "p".to_string(),
+                site_url: "u".to_string(),
+                site_name: None,
+                note: None,
+            }
+        );
+
+        let request =
+            parse_request(br#"{"action": "patch_website_account", "id": 1, "note": null}"#)
+                .unwrap();
+        assert_eq!(
+            request.action,
+            Action::PatchWebsiteAccount {
+                id: 1,
+                account: None,
+                password: None,
+                site_url: None,
+                site_name: None,
+                note: Some(None),
+            }
+        );
+
+        let request = parse_request(
+            br#"{"action": "batch", "actions": [
+                {"action": "add_website_account", "account": "a", "password": "p", "site_url": "u"},
+                {"action": "delete_website_account", "website_id": 2}
+            ]}"#,
+        )
+        .unwrap();
+        match request.action {
+            Action::Batch { actions } => assert_eq!(actions.len(), 2),
+            _ => panic!("Expected batch"),
+        }
+
+        // only website accounts can be changed in a batch
+        assert!(matches!(
+            parse_request(
+                br#"{"action": "batch", "actions": [{"action": "revoke_device", "device_id": 1}]}"#
+            ),
+            Err(ProError::ParseError(_))
+        ));
+
+        assert!(matches!(
+            parse_request(br#"{"action": "no_such_action"}"#),
+            Err(ProError::UnknownAction(action)) if action == "no_such_action"
+        ));
+
+        // every known name is an action, at most some fields are missing
+        for name in ACTION_NAMES {
+            match serde_json::from_value::<Action>(json!({ "action": name })) {
+                Ok(action) => assert_eq!(action.name(), *name),
+                Err(e) => assert!(
+                    e.to_string().starts_with("missing field"),
+                    "{}: {}",
+                    name,
+                    e
+                ),
+            }
+        }
+
+        assert!(matches!(
+            parse_request(br#"{"action": "delete_website_account"}"#),
+            Err(ProError::ParseError(_))
+        ));
+        assert!(matches!(
+            parse_request(b"not json"),
+            Err(ProError::ParseError(_))
+        ));
+    }
+
+    #[test]
+    fn test_json_response() {
+        let response: Value =
+            serde_json::from_str(&json_response(Ok(ProOk::DeadLink(vec![(1, true)])))).unwrap();
+        assert_eq!(
+            response,
+            json!({ "ok": true, "code": 2, "data": [{ "id": 1, "dead_link": true }] })
+        );
+
+        let response: Value =
+            serde_json::from_str(&json_response(Ok(ProOk::Event(Event::Deleted { id: 3 }))))
+                .unwrap();
+        assert_eq!(
+            response,
+            json!({ "ok": true, "code": 15, "data": { "event": "deleted", "id": 3 } })
+        );
+
+        let response: Value =
+            serde_json::from_str(&json_response(Err(ProError::Unauthenticated))).unwrap();
+        assert_eq!(response["ok"], json!(false));
+        assert_eq!(response["code"], json!(6));
+        assert_eq!(response["error"]["kind"], json!("unauthenticated"));
+
+        let response: Value = serde_json::from_str(&json_response(Err(ProError::LockedOut(
+            std::time::Duration::from_secs(8),
+        ))))
+        .unwrap();
+        assert_eq!(response["code"], json!(11));
+        assert_eq!(response["error"]["retry_after"], json!(8));
+
+        let response: Value =
+            serde_json::from_str(&json_response(Err(DbError::NotFound.into()))).unwrap();
+        assert_eq!(response["code"], json!(18));
+        assert_eq!(response["error"]["kind"], json!("not_found"));
+        assert_eq!(response["error"]["message"], json!("No such entry"));
+
+        let response: Value =
+            serde_json::from_str(&json_response(Err(DbError::Crypto.into()))).unwrap();
+        assert_eq!(response["code"], json!(19));
+        assert_eq!(response["error"]["kind"], json!("crypto_error"));
+    }
+}
@@ -0,0 +1,123 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::{Mutex, MutexGuard};
+
+use crate::db::models::AuthFailure;
+use crate::db::{now, Db, DbError};
+
+/// key of the counter shared by every peer
+const GLOBAL: &str = "*";
+
+/// failed attempts of one peer before it gets locked out
+const PEER_FREE_ATTEMPTS: i32 = 3;
+/// failed attempts of all peers together before everyone gets locked out
+const GLOBAL_FREE_ATTEMPTS: i32 = 20;
+
+/// the first lockout, doubled by every further failed attempt
+const BASE_LOCKOUT: Duration = Duration::from_secs(1);
+const MAX_LOCKOUT: Duration = Duration::from_secs(15 * 60);
+/// the lockout of an attempt which could not be counted
+pub const UNCOUNTED_LOCKOUT: Duration = BASE_LOCKOUT;
+
+/// Brute-force protection for CheckIdentity
+///
+/// Failed attempts are counted per peer and for all peers together.
+/// After the free attempts every further failure locks the peer
+/// (or everyone, for the global counter) out for an exponentially
+/// growing time. A successful attempt resets both counters.
+///
+/// An attempt is recorded as a failure before it is checked,
+/// `record_success` takes it back afterwards.
+///
+/// The counters live in the database, so a restart does not reset them.
+pub struct Lockout {
+    db: Arc<Db>,
+    /// one attempt at a time, so parallel attempts can't race the counters
+    attempt: Mutex<()>,
+}
+
+impl Lockout {
+    pub fn new(db: Arc<Db>) -> Self {
+        Lockout {
+            db,
+            attempt: Mutex::new(()),
+        }
+    }
+
+    /// wait for the other attempts, hold the guard until the attempt is recorded
+    pub async fn begin(&self) -> MutexGuard<'_, ()> {
+        self.attempt.lock().await
+    }
+
+    /// the remaining lockout of the peer, if it is locked out
+    pub async fn locked_for(&self, peer: &str) -> Result<Option<Duration>, DbError> {
+        let now = now();
+        let mut locked_until = 0;
+        for key in [peer, GLOBAL] {
+            if let Some(failure) = self.db.get_auth_failure(key).await? {
+                locked_until = locked_until.max(failure.locked_until);
+            }
+        }
+
+        if locked_until > now {
+            Ok(Some(Duration::from_secs((locked_until - now) as u64)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub async fn record_failure(&self, peer: &str) -> Result<(), DbError> {
+        for (key, free_attempts) in [(peer, PEER_FREE_ATTEMPTS), (GLOBAL, GLOBAL_FREE_ATTEMPTS)] {
+            let failures = match self.db.get_auth_failure(key).await? {
+                Some(failure) => failure.failures.saturating_add(1),
+                None => 1,
+            };
+            let locked_until = match lockout_duration(failures, free_attempts) {
+                Some(duration) => now() + duration.as_secs() as i64,
+                None => 0,
+            };
+
+            self.db
+                .set_auth_failure(AuthFailure {
+                    peer: key.to_string(),
+                    failures,
+                    locked_until,
+                })
+                .await?;
+        }
+        Ok(())
+    }
+
+    pub async fn record_success(&self, peer: &str) -> Result<(), DbError> {
+        self.db.delete_auth_failure(peer).await?;
+        self.db.delete_auth_failure(GLOBAL).await
+    }
+}
+
+/// how long to lock out after `failures` failed attempts
+fn lockout_duration(failures: i32, free_attempts: i32) -> Option<Duration> {
+    if failures <= free_attempts {
+        return None;
+    }
+
+    // 2^20 seconds is well past the maximum already
+    let exponent = (failures - free_attempts - 1).min(20) as u32;
+    Some((BASE_LOCKOUT * 2u32.pow(exponent)).min(MAX_LOCKOUT))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lockout_duration() {
+        assert_eq!(lockout_duration(1, 3), None);
+        assert_eq!(lockout_duration(3, 3), None);
+        assert_eq!(lockout_duration(4, 3), Some(Duration::from_secs(1)));
+        assert_eq!(lockout_duration(5, 3), Some(Duration::from_secs(2)));
+        assert_eq!(lockout_duration(8, 3), Some(Duration::from_secs(16)));
+        assert_eq!(lockout_duration(100, 3), Some(MAX_LOCKOUT));
+        assert_eq!(lockout_duration(i32::MAX, 3), Some(MAX_LOCKOUT));
+    }
+}
@@ -1,12 +1,209 @@
-use crate::db::models::WebsiteAccountWithDeadLink;
+use std::fmt;
+use std::time::Duration;
 
+use super::auth::AuthError;
+use crate::db::event::Event;
+use crate::db::models::{AuditLog, Device, WebsiteAccountWithDeadLink};
+use crate::db::{BatchError, DbError};
+
+#[derive(Debug)]
 pub enum ProError {
     DbError(diesel::result::Error),
-    IdentityError(pam::PamError),
+    /// the password was wrong
+    IdentityError,
+    Unauthenticated,
+    UnsupportedVersion(u8),
+    NoClientCertificate,
+    /// too many failed CheckIdentity attempts, retry after the duration
+    LockedOut(Duration),
+    /// the batch was rolled back
+    BatchError(BatchError),
+    /// the request could not be read
+    ParseError(String),
+    /// the request names an action which does not exist
+    UnknownAction(String),
+    /// the website account or device does not exist
+    NotFound,
+    /// a password could not be encrypted or decrypted
+    CryptoError,
+    /// no database connection could be taken
+    PoolUnavailable(String),
+    /// a service the daemon relies on, like PAM, failed
+    UpstreamError(String),
+    /// the action can't be used with this configuration
+    Unsupported(String),
+    /// the vault is locked, CheckIdentity unlocks it
+    Locked,
+    /// TOTP is enabled and no code was given, the password is not checked then
+    TotpRequired,
+}
+
+impl From<DbError> for ProError {
+    fn from(e: DbError) -> Self {
+        match e {
+            DbError::Query(e) => ProError::DbError(e),
+            DbError::NotFound => ProError::NotFound,
+            DbError::Crypto => ProError::CryptoError,
+            DbError::PoolUnavailable(e) => ProError::PoolUnavailable(e),
+            DbError::Locked => ProError::Locked,
+            e @ DbError::KeyMissing => ProError::Unsupported(e.to_string()),
+        }
+    }
 }
 
 pub enum ProOk {
     Ack,
-    Info(Vec<WebsiteAccountWithDeadLink>),
+    Session {
+        token: String,
+        expires_in: u64,
+    },
+    /// one page of website accounts, `total` counts all of them
+    Info {
+        total: usize,
+        list: Vec<WebsiteAccountWithDeadLink>,
+    },
     DeadLink(Vec<(i32, bool)>),
+    Devices(Vec<Device>),
+    Password(String),
+    /// one page of the audit log, `valid` is false if the hash chain is broken
+    AuditLog {
+        total: usize,
+        valid: bool,
+        list: Vec<AuditLog>,
+    },
+    /// the id of the website account of every item of the batch
+    Batch(Vec<i32>),
+    /// a change pushed to a subscribed connection, not an answer to a request
+    Event(Event),
+    /// the otpauth URI of a new TOTP secret
+    TotpUri(String),
+    /// the one-time recovery codes, only shown once
+    RecoveryCodes(Vec<String>),
+}
+
+impl ProOk {
+    /// response code, shared by every protocol version
+    pub fn code(&self) -> u8 {
+        match self {
+            ProOk::Ack => 0,
+            ProOk::Info { .. } => 1,
+            ProOk::DeadLink(_) => 2,
+            ProOk::Session { .. } => 5,
+            ProOk::Devices(_) => 8,
+            ProOk::Password(_) => 10,
+            ProOk::AuditLog { .. } => 12,
+            ProOk::Batch(_) => 13,
+            ProOk::Event(_) => 15,
+            ProOk::TotpUri(_) => 25,
+            ProOk::RecoveryCodes(_) => 26,
+        }
+    }
+}
+
+impl ProError {
+    /// response code, shared by every protocol version
+    pub fn code(&self) -> u8 {
+        match self {
+            ProError::IdentityError => 3,
+            ProError::DbError(_) => 4,
+            ProError::Unauthenticated => 6,
+            ProError::UnsupportedVersion(_) => 7,
+            ProError::NoClientCertificate => 9,
+            ProError::LockedOut(_) => 11,
+            ProError::BatchError(_) => 14,
+            ProError::ParseError(_) => 16,
+            ProError::UnknownAction(_) => 17,
+            ProError::NotFound => 18,
+            ProError::CryptoError => 19,
+            ProError::PoolUnavailable(_) => 20,
+            ProError::UpstreamError(_) => 21,
+            ProError::Unsupported(_) => 22,
+            ProError::Locked => 23,
+            ProError::TotpRequired => 24,
+        }
+    }
+
+    /// name of the error used by the JSON protocol
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ProError::IdentityError => "identity_error",
+            ProError::DbError(_) => "db_error",
+            ProError::Unauthenticated => "unauthenticated",
+            ProError::UnsupportedVersion(_) => "unsupported_version",
+            ProError::NoClientCertificate => "no_client_certificate",
+            ProError::LockedOut(_) => "locked_out",
+            ProError::BatchError(_) => "batch_error",
+            ProError::ParseError(_) => "parse_error",
+            ProError::UnknownAction(_) => "unknown_action",
+            ProError::NotFound => "not_found",
+            ProError::CryptoError => "crypto_error",
+            ProError::PoolUnavailable(_) => "pool_unavailable",
+            ProError::UpstreamError(_) => "upstream_error",
+            ProError::Unsupported(_) => "unsupported",
+            ProError::Locked => "locked",
+            ProError::TotpRequired => "totp_required",
+        }
+    }
+}
+
+impl fmt::Display for ProError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProError::IdentityError => write!(f, "Identity check failed: wrong password or code"),
+            ProError::DbError(e) => write!(f, "Database error: {}", e),
+            ProError::Unauthenticated => write!(f, "Session is missing or expired"),
+            ProError::UnsupportedVersion(v) => write!(f, "Protocol version {} is not supported", v),
+            ProError::NoClientCertificate => write!(f, "No client certificate was presented"),
+            ProError::LockedOut(d) => write!(
+                f,
+                "Too many failed attempts, try again in {} seconds",
+                d.as_secs()
+            ),
+            ProError::BatchError(BatchError { index, error }) => match index {
+                Some(index) => write!(
+                    f,
+                    "Item {} of the batch failed, nothing was changed: {}",
+                    index, error
+                ),
+                None => write!(f, "The batch failed, nothing was changed: {}", error),
+            },
+            ProError::ParseError(e) => write!(f, "Invalid request: {}", e),
+            ProError::UnknownAction(action) => write!(f, "Unknown action: {}", action),
+            ProError::NotFound => write!(f, "No such entry"),
+            ProError::CryptoError => write!(f, "Failed to encrypt or decrypt the password"),
+            ProError::PoolUnavailable(e) => write!(f, "Database is unavailable: {}", e),
+            ProError::UpstreamError(e) => write!(f, "Upstream service failed: {}", e),
+            ProError::Unsupported(e) => write!(f, "Not supported: {}", e),
+            ProError::Locked => write!(f, "The vault is locked, check the identity to unlock it"),
+            ProError::TotpRequired => write!(f, "A TOTP or recovery code is needed as well"),
+        }
+    }
+}
+
+impl From<AuthError> for ProError {
+    fn from(e: AuthError) -> Self {
+        match e {
+            AuthError::Rejected => ProError::IdentityError,
+            AuthError::Unavailable(_) => ProError::UpstreamError(e.to_string()),
+            AuthError::Unsupported => ProError::Unsupported(e.to_string()),
+        }
+    }
+}
+
+impl From<&str> for ProError {
+    fn from(e: &str) -> Self {
+        ProError::ParseError(e.to_string())
+    }
+}
+
+impl From<String> for ProError {
+    fn from(e: String) -> Self {
+        ProError::ParseError(e)
+    }
+}
+
+impl From<std::num::ParseIntError> for ProError {
+    fn from(e: std::num::ParseIntError) -> Self {
+        ProError::ParseError(e.to_string())
+    }
 }
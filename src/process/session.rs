@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+
+/// How long a token handed out by `CheckIdentity` stays valid
+pub const SESSION_TTL: Duration = Duration::from_secs(15 * 60);
+
+struct Session {
+    user: String,
+    expiry: Instant,
+}
+
+/// Session tokens issued after a successful `CheckIdentity`
+///
+/// Every other action has to present one of these tokens,
+/// a token is dropped once it is expired or revoked.
+pub struct Sessions {
+    tokens: Mutex<HashMap<String, Session>>,
+    ttl: Duration,
+}
+
+impl Sessions {
+    pub fn new(ttl: Duration) -> Self {
+        Sessions {
+            tokens: Mutex::new(HashMap::new()),
+            ttl,
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// create a new token for the user, and forget the expired ones
+    pub fn create(&self, user: String) -> String {
+        let mut bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut bytes);
+        let token = URL_SAFE_NO_PAD.encode(bytes);
+
+        let now = Instant::now();
+        let mut tokens = self.tokens.lock().unwrap();
+        tokens.retain(|_, session| session.expiry > now);
+        tokens.insert(
+            token.clone(),
+            Session {
+                user,
+                expiry: now + self.ttl,
+            },
+        );
+
+        token
+    }
+
+    /// check whether the token exists and is not expired
+    /// return the user of the session
+    pub fn validate(&self, token: &str) -> Option<String> {
+        let mut tokens = self.tokens.lock().unwrap();
+        match tokens.get(token) {
+            Some(session) if session.expiry > Instant::now() => Some(session.user.clone()),
+            Some(_) => {
+                tokens.remove(token);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// forget the token, used by `Logout`
+    pub fn revoke(&self, token: &str) {
+        self.tokens.lock().unwrap().remove(token);
+    }
+}
+
+impl Default for Sessions {
+    fn default() -> Self {
+        Sessions::new(SESSION_TTL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sessions() {
+        let sessions = Sessions::default();
+        let token = sessions.create("me".to_string());
+
+        assert_eq!(sessions.validate(&token), Some("me".to_string()));
+        assert_eq!(sessions.validate("not_a_token"), None);
+
+        sessions.revoke(&token);
+        assert_eq!(sessions.validate(&token), None);
+
+        let sessions = Sessions::new(Duration::from_millis(10));
+        let token = sessions.create("me".to_string());
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert_eq!(sessions.validate(&token), None);
+    }
+}
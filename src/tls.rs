@@ -0,0 +1,277 @@
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use sha2::{Digest, Sha256};
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::client::danger::HandshakeSignatureValid;
+use tokio_rustls::rustls::crypto::{
+    ring, verify_tls12_signature, verify_tls13_signature, CryptoProvider,
+};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use tokio_rustls::rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use tokio_rustls::rustls::{
+    ClientConfig, DigitallySignedStruct, DistinguishedName, RootCertStore, ServerConfig,
+    SignatureScheme,
+};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+/// build the TLS acceptor from a PEM certificate chain and a PEM private key
+///
+/// With `client_auth` the client is asked for a certificate. Any certificate
+/// is accepted by the handshake, whether it belongs to an enrolled device
+/// is checked against the database by its `fingerprint` afterwards.
+pub fn acceptor(cert_path: &Path, key_path: &Path, client_auth: bool) -> io::Result<TlsAcceptor> {
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(io::Error::other)?;
+    let key = PrivateKeyDer::from_pem_file(key_path).map_err(io::Error::other)?;
+
+    let builder = ServerConfig::builder();
+    let builder = if client_auth {
+        builder.with_client_cert_verifier(Arc::new(AnyClientCert {
+            provider: Arc::new(ring::default_provider()),
+        }))
+    } else {
+        builder.with_no_client_auth()
+    };
+
+    let config = builder
+        .with_single_cert(certs, key)
+        .map_err(io::Error::other)?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// build the TLS connector of a client, trusting the PEM certificates in `ca_path`,
+/// the self-signed certificate of the daemon or its CA
+///
+/// With `client_cert`, a PEM certificate chain and a PEM private key,
+/// the client presents it, an enrolled device needs no password then.
+pub fn connector(ca_path: &Path, client_cert: Option<(&Path, &Path)>) -> io::Result<TlsConnector> {
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_file_iter(ca_path).map_err(io::Error::other)? {
+        roots
+            .add(cert.map_err(io::Error::other)?)
+            .map_err(io::Error::other)?;
+    }
+
+    let builder = ClientConfig::builder().with_root_certificates(roots);
+    let config = match client_cert {
+        Some((cert_path, key_path)) => {
+            let certs = CertificateDer::pem_file_iter(cert_path)
+                .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+                .map_err(io::Error::other)?;
+            let key = PrivateKeyDer::from_pem_file(key_path).map_err(io::Error::other)?;
+            builder
+                .with_client_auth_cert(certs, key)
+                .map_err(io::Error::other)?
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+/// connect to the daemon at `addr` (`HOST:PORT`) over TLS,
+/// its certificate has to be made out to HOST
+pub async fn connect(addr: &str, connector: &TlsConnector) -> io::Result<TlsStream<TcpStream>> {
+    let name = ServerName::try_from(host_of(addr).to_string()).map_err(io::Error::other)?;
+    let stream = TcpStream::connect(addr).await?;
+    connector.connect(name, stream).await
+}
+
+/// the host of `HOST:PORT`, without the brackets of an IPv6 address
+fn host_of(addr: &str) -> &str {
+    let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host);
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+/// SHA-256 of the DER certificate, in hex
+pub fn fingerprint(cert: &CertificateDer<'_>) -> String {
+    Sha256::digest(cert.as_ref())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// fingerprint of the certificate the client presented, if any
+pub fn peer_fingerprint<S>(stream: &tokio_rustls::server::TlsStream<S>) -> Option<String> {
+    let (_, session) = stream.get_ref();
+    session
+        .peer_certificates()
+        .and_then(|certs| certs.first())
+        .map(fingerprint)
+}
+
+/// Accept every client certificate, but still check that the client owns its key
+#[derive(Debug)]
+struct AnyClientCert {
+    provider: Arc<CryptoProvider>,
+}
+
+impl ClientCertVerifier for AnyClientCert {
+    fn client_auth_mandatory(&self) -> bool {
+        // clients without a certificate can still use a password
+        false
+    }
+
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _now: UnixTime,
+    ) -> Result<ClientCertVerified, tokio_rustls::rustls::Error> {
+        Ok(ClientCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    async fn handshake(client_cert: Option<&rcgen::CertifiedKey>) -> Option<String> {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+
+        let dir = std::env::temp_dir();
+        let id = format!("{}_{}", std::process::id(), client_cert.is_some());
+        let cert_path = dir.join(format!("ysnp_test_cert_{}.pem", id));
+        let key_path = dir.join(format!("ysnp_test_key_{}.pem", id));
+        std::fs::write(&cert_path, cert.cert.pem()).unwrap();
+        std::fs::write(&key_path, cert.key_pair.serialize_pem()).unwrap();
+
+        let acceptor = acceptor(&cert_path, &key_path, true).unwrap();
+        std::fs::remove_file(&cert_path).unwrap();
+        std::fs::remove_file(&key_path).unwrap();
+
+        let mut roots = RootCertStore::empty();
+        roots.add(cert.cert.der().clone()).unwrap();
+        let builder = ClientConfig::builder().with_root_certificates(roots);
+        let config = match client_cert {
+            Some(client) => builder
+                .with_client_auth_cert(
+                    vec![client.cert.der().clone()],
+                    PrivateKeyDer::try_from(client.key_pair.serialize_der()).unwrap(),
+                )
+                .unwrap(),
+            None => builder.with_no_client_auth(),
+        };
+        let connector = TlsConnector::from(Arc::new(config));
+
+        let (client, server) = tokio::io::duplex(4096);
+        let server = tokio::spawn(async move {
+            let mut stream = acceptor.accept(server).await.unwrap();
+            let mut buffer = [0; 5];
+            stream.read_exact(&mut buffer).await.unwrap();
+            assert_eq!(&buffer, b"hello");
+            peer_fingerprint(&stream)
+        });
+
+        let name = ServerName::try_from("localhost").unwrap();
+        let mut stream = connector.connect(name, client).await.unwrap();
+        stream.write_all(b"hello").await.unwrap();
+        stream.flush().await.unwrap();
+
+        server.await.unwrap()
+    }
+
+    #[test]
+    fn test_host_of() {
+        assert_eq!(host_of("127.0.0.1:6123"), "127.0.0.1");
+        assert_eq!(host_of("localhost:6123"), "localhost");
+        assert_eq!(host_of("[::1]:6123"), "::1");
+        assert_eq!(host_of("localhost"), "localhost");
+    }
+
+    #[tokio::test]
+    async fn test_connect() {
+        let server = rcgen::generate_simple_self_signed(vec!["127.0.0.1".to_string()]).unwrap();
+        let client = rcgen::generate_simple_self_signed(vec!["my_laptop".to_string()]).unwrap();
+
+        let dir = std::env::temp_dir();
+        let path = |name: &str| {
+            dir.join(format!(
+                "ysnp_test_connect_{}_{}.pem",
+                name,
+                std::process::id()
+            ))
+        };
+        let files = [
+            (path("cert"), server.cert.pem()),
+            (path("key"), server.key_pair.serialize_pem()),
+            (path("client_cert"), client.cert.pem()),
+            (path("client_key"), client.key_pair.serialize_pem()),
+        ];
+        for (path, pem) in &files {
+            std::fs::write(path, pem).unwrap();
+        }
+        let acceptor = acceptor(&files[0].0, &files[1].0, true).unwrap();
+        let connector = connector(&files[0].0, Some((&files[2].0, &files[3].0))).unwrap();
+        for (path, _) in &files {
+            std::fs::remove_file(path).unwrap();
+        }
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let stream = acceptor.accept(socket).await.unwrap();
+            peer_fingerprint(&stream)
+        });
+
+        connect(&addr, &connector).await.unwrap();
+        assert_eq!(server.await.unwrap(), Some(fingerprint(client.cert.der())));
+    }
+
+    #[tokio::test]
+    async fn test_acceptor() {
+        assert_eq!(handshake(None).await, None);
+
+        let client = rcgen::generate_simple_self_signed(vec!["my_laptop".to_string()]).unwrap();
+        assert_eq!(
+            handshake(Some(&client)).await,
+            Some(fingerprint(client.cert.der()))
+        );
+    }
+}
@@ -0,0 +1,223 @@
+use aes_gcm::aead::OsRng;
+use argon2::password_hash::rand_core::RngCore;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+/// RFC 6238 with the defaults every authenticator app understands:
+/// HMAC-SHA1, 6 digits and a step of 30 seconds
+pub const DIGITS: u32 = 6;
+pub const PERIOD: u64 = 30;
+/// how many steps the clock of the app may be off
+const SKEW: u64 = 1;
+
+const ISSUER: &str = "you_should_not_pass";
+const BASE32: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// how many recovery codes are handed out on enrollment
+pub const RECOVERY_CODES: usize = 10;
+
+/// a random 160-bit secret, as RFC 4226 recommends
+pub fn generate_secret() -> Vec<u8> {
+    let mut secret = vec![0u8; 20];
+    OsRng.fill_bytes(&mut secret);
+    secret
+}
+
+/// RFC 4648 base32 without padding, the form of the secret in the otpauth URI
+pub fn to_base32(data: &[u8]) -> String {
+    let mut result = String::new();
+    for chunk in data.chunks(5) {
+        let mut buffer = [0u8; 5];
+        buffer[..chunk.len()].copy_from_slice(chunk);
+        let bits = buffer
+            .iter()
+            .fold(0u64, |bits, byte| bits << 8 | *byte as u64);
+
+        let chars = (chunk.len() * 8).div_ceil(5);
+        for i in 0..chars {
+            let index = (bits >> (35 - i * 5)) & 0x1f;
+            result.push(BASE32[index as usize] as char);
+        }
+    }
+    result
+}
+
+/// `None` if it is not base32, case and padding are ignored
+pub fn from_base32(data: &str) -> Option<Vec<u8>> {
+    let mut result = Vec::new();
+    let (mut bits, mut count) = (0u64, 0);
+    for c in data.trim_end_matches('=').chars() {
+        let value = BASE32
+            .iter()
+            .position(|b| *b as char == c.to_ascii_uppercase())?;
+        bits = bits << 5 | value as u64;
+        count += 5;
+        if count >= 8 {
+            count -= 8;
+            result.push((bits >> count) as u8);
+        }
+    }
+    Some(result)
+}
+
+/// the time step of a unix time
+pub fn step_of(unix_time: u64) -> u64 {
+    unix_time / PERIOD
+}
+
+/// the code of one time step, zero padded
+pub fn code_at(secret: &[u8], step: u64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC takes any key length");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    // dynamic truncation of RFC 4226
+    let offset = (hash[hash.len() - 1] & 0xf) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+
+    format!(
+        "{:0width$}",
+        binary % 10u32.pow(DIGITS),
+        width = DIGITS as usize
+    )
+}
+
+/// the time step `code` belongs to, within `SKEW` steps of `unix_time`
+///
+/// Steps up to `last_step` were used already and are refused,
+/// so a code can't be replayed.
+pub fn verify(secret: &[u8], code: &str, unix_time: u64, last_step: Option<u64>) -> Option<u64> {
+    let now = step_of(unix_time);
+    (now.saturating_sub(SKEW)..=now + SKEW)
+        .filter(|step| last_step.is_none_or(|last| *step > last))
+        .find(|step| code_at(secret, *step) == code)
+}
+
+/// the URI to enroll the secret, usually shown as a QR code
+pub fn otpauth_uri(secret: &[u8], account: &str) -> String {
+    let label = format!("{}:{}", ISSUER, account);
+    format!(
+        "otpauth://totp/{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        percent_encode(&label),
+        to_base32(secret),
+        percent_encode(ISSUER),
+        DIGITS,
+        PERIOD
+    )
+}
+
+fn percent_encode(data: &str) -> String {
+    data.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b':' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// `RECOVERY_CODES` random codes like `abcde-fghij`, each can be used once instead of a TOTP code
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODES)
+        .map(|_| {
+            // 50 random bits
+            let mut bytes = [0u8; 7];
+            OsRng.fill_bytes(&mut bytes);
+            let code = to_base32(&bytes).to_lowercase();
+            format!("{}-{}", &code[..5], &code[5..10])
+        })
+        .collect()
+}
+
+/// the hash of a recovery code to store, dashes, spaces and case don't matter
+///
+/// The codes are random, so a plain SHA-256 is enough.
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| *c != '-' && !c.is_whitespace())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    Sha256::digest(normalized.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// whether `code` looks like a TOTP code rather than a recovery code
+pub fn is_totp_code(code: &str) -> bool {
+    code.len() == DIGITS as usize && code.bytes().all(|b| b.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base32() {
+        // RFC 4648
+        assert_eq!(to_base32(b""), "");
+        assert_eq!(to_base32(b"f"), "MY");
+        assert_eq!(to_base32(b"foob"), "MZXW6YQ");
+        assert_eq!(to_base32(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(from_base32("MZXW6YTBOI======").unwrap(), b"foobar");
+        assert_eq!(from_base32("mzxw6yq").unwrap(), b"foob");
+        assert!(from_base32("not base32!").is_none());
+
+        let secret = generate_secret();
+        assert_eq!(from_base32(&to_base32(&secret)).unwrap(), secret);
+    }
+
+    #[test]
+    fn test_code() {
+        // RFC 6238 appendix B, SHA1, cut to 6 digits
+        let secret = b"12345678901234567890";
+        for (time, code) in [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+        ] {
+            assert_eq!(code_at(secret, step_of(time)), code);
+        }
+
+        let step = verify(secret, "081804", 1111111109 + PERIOD, None).unwrap();
+        assert_eq!(step, step_of(1111111109));
+        // too old, or used already
+        assert!(verify(secret, "081804", 1111111109 + 2 * PERIOD, None).is_none());
+        assert!(verify(secret, "081804", 1111111109, Some(step)).is_none());
+        assert!(verify(secret, "000000", 1111111109, None).is_none());
+    }
+
+    #[test]
+    fn test_otpauth_uri() {
+        let uri = otpauth_uri(b"foobar", "me and you");
+        assert_eq!(
+            uri,
+            "otpauth://totp/you_should_not_pass:me%20and%20you?secret=MZXW6YTBOI\
+             &issuer=you_should_not_pass&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+
+    #[test]
+    fn test_recovery_codes() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODES);
+        assert!(codes
+            .iter()
+            .all(|code| code.len() == 11 && !is_totp_code(code)));
+        assert_eq!(
+            hash_recovery_code(&codes[0]),
+            hash_recovery_code(&codes[0].replace('-', " ").to_uppercase())
+        );
+        assert_ne!(hash_recovery_code(&codes[0]), hash_recovery_code(&codes[1]));
+    }
+}
@@ -0,0 +1,59 @@
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use tokio::net::{UnixListener, UnixStream};
+
+/// bind the unix socket, only its owner can read and write it
+///
+/// A socket left behind by a previous run is removed first.
+pub fn bind(path: &Path) -> io::Result<UnixListener> {
+    match fs::remove_file(path) {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+
+    Ok(listener)
+}
+
+/// check the peer with SO_PEERCRED,
+/// only the user running the daemon owns the vault
+pub fn is_owner(stream: &UnixStream) -> bool {
+    match stream.peer_cred() {
+        // SAFETY: getuid is always successful
+        Ok(cred) => cred.uid() == unsafe { libc::getuid() },
+        Err(e) => {
+            eprintln!("Failed to get peer credentials: {}", e);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_unix_socket() {
+        let path = std::env::temp_dir().join(format!("ysnp_test_{}.sock", std::process::id()));
+
+        let listener = bind(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let _client = UnixStream::connect(&path).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        assert!(is_owner(&server));
+
+        // a stale socket does not stop us
+        drop(listener);
+        assert!(bind(&path).is_ok());
+
+        fs::remove_file(&path).unwrap();
+    }
+}